@@ -9,7 +9,13 @@ use axum::{
 };
 use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
 use color_eyre::eyre::WrapErr;
-use judge::{contest::Contest, submit::submit, CONTESTS};
+use judge::{
+    contest::Contest,
+    sandbox::{self, Sandbox, SlotPool},
+    submit::{compile_checkers, submit, submit_answer},
+    CONTESTS,
+};
+use once_cell::sync::{Lazy, OnceCell};
 use thiserror::Error;
 use tokio::{fs, net::TcpListener, sync::mpsc};
 use tokio_stream::wrappers::ReceiverStream;
@@ -23,12 +29,20 @@ use tracing_subscriber::{prelude::*, EnvFilter};
 use tracing_tree::HierarchicalLayer;
 use uuid::Uuid;
 
+const MAX_CONCURRENT_SUBMISSIONS: usize = 5;
+
+static SANDBOX: OnceCell<Box<dyn Sandbox>> = OnceCell::new();
+/// Each submission is judged in its own slot, so this also limits concurrent submissions
+static SLOTS: Lazy<SlotPool> = Lazy::new(|| SlotPool::new(MAX_CONCURRENT_SUBMISSIONS));
+
 #[derive(TryFromMultipart)]
 struct SubmitRequest {
     contest: String,
     task: usize,
-    language: String,
-    code: String,
+    language: Option<String>,
+    code: Option<String>,
+    /// Submitted instead of a language and code, for tasks which accept answers
+    answer: Option<String>,
 }
 
 type Stream = Sse<ReceiverStream<Result<Event, std::convert::Infallible>>>;
@@ -41,6 +55,10 @@ enum SubmitError {
     TaskNotFound(String, usize),
     #[error("unsupported language: {0}")]
     UnsupportedLanguage(String),
+    #[error("task #{1} for contest {0} doesn't accept answers")]
+    NoAnswer(String, usize),
+    #[error("submission has neither an answer nor a language and code")]
+    MissingCode,
     #[error("IO error: {0}")]
     Io(#[from] tokio::io::Error),
 }
@@ -51,7 +69,9 @@ impl IntoResponse for SubmitError {
             SubmitError::ContestNotFound(_) | SubmitError::TaskNotFound(_, _) => {
                 StatusCode::NOT_FOUND
             }
-            SubmitError::UnsupportedLanguage(_) => StatusCode::BAD_REQUEST,
+            SubmitError::UnsupportedLanguage(_)
+            | SubmitError::NoAnswer(_, _)
+            | SubmitError::MissingCode => StatusCode::BAD_REQUEST,
             SubmitError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -59,13 +79,14 @@ impl IntoResponse for SubmitError {
     }
 }
 
-#[tracing::instrument(skip(language_name, code), err)]
+#[tracing::instrument(skip(language_name, code, answer), err)]
 async fn handler(
     TypedMultipart(SubmitRequest {
         contest: contest_name,
         task: task_index,
         language: language_name,
         code,
+        answer,
     }): TypedMultipart<SubmitRequest>,
 ) -> Result<Stream, SubmitError> {
     let contests = CONTESTS.get().unwrap();
@@ -77,14 +98,28 @@ async fn handler(
     let task = task_index
         .checked_sub(1)
         .and_then(|idx| contest.tasks.get(idx))
-        .ok_or_else(|| SubmitError::TaskNotFound(contest_name, task_index))?;
+        .ok_or_else(|| SubmitError::TaskNotFound(contest_name.clone(), task_index))?;
+
+    if let Some(answer) = answer {
+        if task.answer.is_none() {
+            return Err(SubmitError::NoAnswer(contest_name, task_index));
+        }
+
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(submit_answer(tx, &contest.scoring, task, answer));
+        return Ok(Sse::new(ReceiverStream::new(rx)));
+    }
+
+    let (Some(language_name), Some(code)) = (language_name, code) else {
+        return Err(SubmitError::MissingCode);
+    };
 
     let language = contest
         .config
         .languages
         .iter()
         .find(|lang| lang.name == language_name)
-        .ok_or_else(|| SubmitError::UnsupportedLanguage(language_name))?;
+        .ok_or(SubmitError::UnsupportedLanguage(language_name))?;
 
     let uuid = Uuid::new_v4();
     let dir = Path::new("submissions").join(uuid.to_string());
@@ -92,7 +127,8 @@ async fn handler(
     fs::write(dir.join(&language.filename), code).await?;
 
     let (tx, rx) = mpsc::channel(64);
-    tokio::spawn(submit(tx, dir, &contest.config, task, language));
+    let sandbox = SANDBOX.get().unwrap().as_ref();
+    tokio::spawn(submit(tx, dir, contest, task, language, sandbox, &SLOTS));
 
     Ok(Sse::new(ReceiverStream::new(rx)))
 }
@@ -108,6 +144,9 @@ async fn main() -> color_eyre::Result<()> {
         .try_init()
         .wrap_err("failed to initialize tracing")?;
 
+    SANDBOX.set(sandbox::init()).unwrap();
+    let sandbox = SANDBOX.get().unwrap().as_ref();
+
     let contests = {
         let mut contests = AHashMap::new();
 
@@ -117,17 +156,24 @@ async fn main() -> color_eyre::Result<()> {
         while let Some(entry) = read_dir.next_entry().await? {
             let path = entry.path();
             if path.extension().and_then(OsStr::to_str) == Some("json") {
+                let name = path
+                    .file_stem()
+                    .unwrap()
+                    .to_str()
+                    .expect("non UTF-8 filename")
+                    .to_owned();
                 let input = fs::read_to_string(&path).await?;
-                let contest = Contest::load(&input)?;
+                let mut contest = Contest::load(&input, path.parent().unwrap())?;
+                compile_checkers(
+                    &mut contest,
+                    &Path::new("checkers").join(&name),
+                    sandbox,
+                    &SLOTS,
+                )
+                .await
+                .wrap_err_with(|| format!("failed to compile checkers of {}", path.display()))?;
                 tracing::info!("loaded contest {} ({})", contest.name, path.display());
-                contests.insert(
-                    path.file_stem()
-                        .unwrap()
-                        .to_str()
-                        .expect("non UTF-8 filename")
-                        .to_owned(),
-                    contest,
-                );
+                contests.insert(name, contest);
             }
         }
 
@@ -0,0 +1,246 @@
+use std::str;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// How the output of a submission is compared to the expected output. Both are compared as bytes,
+/// so output which isn't valid UTF-8 can still be accepted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compare {
+    /// The same bytes, apart from whitespace at the start and end
+    #[default]
+    Trimmed,
+    /// The same bytes
+    Exact,
+    /// The same tokens, separated by any whitespace
+    Tokens,
+    /// The same lines, ignoring whitespace at the end of each line and blank lines at the end
+    Lines,
+    /// The same tokens, where numbers may differ by up to `absolute` or up to `relative` times
+    /// the expected number
+    #[serde(rename_all = "kebab-case")]
+    Float {
+        #[serde(default)]
+        absolute: f64,
+        #[serde(default)]
+        relative: f64,
+    },
+    /// The same tokens, ignoring the case of ASCII letters
+    CaseInsensitive,
+    /// The same lines in any order, ignoring whitespace at the end of each line and blank lines
+    UnorderedLines,
+}
+
+impl Compare {
+    /// Whether `output` is accepted as the `expected` output.
+    pub fn matches(&self, output: &[u8], expected: &[u8]) -> bool {
+        match *self {
+            Compare::Trimmed => output.trim_ascii() == expected.trim_ascii(),
+            Compare::Exact => output == expected,
+            Compare::Tokens => tokens(output).eq(tokens(expected)),
+            Compare::Lines => lines(output) == lines(expected),
+            Compare::Float { absolute, relative } => {
+                eq_by(tokens(output), tokens(expected), |output, expected| {
+                    output == expected || close(output, expected, absolute, relative)
+                })
+            }
+            Compare::CaseInsensitive => eq_by(
+                tokens(output),
+                tokens(expected),
+                <[u8]>::eq_ignore_ascii_case,
+            ),
+            Compare::UnorderedLines => {
+                let sorted = |bytes| {
+                    let mut lines = lines(bytes);
+                    lines.retain(|line| !line.is_empty());
+                    lines.sort_unstable();
+                    lines
+                };
+                sorted(output) == sorted(expected)
+            }
+        }
+    }
+}
+
+fn tokens(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    bytes
+        .split(u8::is_ascii_whitespace)
+        .filter(|token| !token.is_empty())
+}
+
+/// Lines without the whitespace at their end, such as a carriage return, and without blank lines
+/// at the end.
+fn lines(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut lines = bytes
+        .split(|&byte| byte == b'\n')
+        .map(<[u8]>::trim_ascii_end)
+        .collect::<Vec<_>>();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn eq_by<'a>(
+    mut output: impl Iterator<Item = &'a [u8]>,
+    mut expected: impl Iterator<Item = &'a [u8]>,
+    eq: impl Fn(&[u8], &[u8]) -> bool,
+) -> bool {
+    loop {
+        match (output.next(), expected.next()) {
+            (None, None) => return true,
+            (Some(output), Some(expected)) if eq(output, expected) => {}
+            _ => return false,
+        }
+    }
+}
+
+fn close(output: &[u8], expected: &[u8], absolute: f64, relative: f64) -> bool {
+    let (Some(output), Some(expected)) = (number(output), number(expected)) else {
+        return false;
+    };
+
+    let error = (output - expected).abs();
+    error <= absolute || error <= relative * expected.abs()
+}
+
+/// Parses a finite number, so that `inf` and `nan` are only accepted as they are.
+fn number(token: &[u8]) -> Option<f64> {
+    str::from_utf8(token)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|number: &f64| number.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(compare: Compare, output: &str, expected: &str) -> bool {
+        compare.matches(output.as_bytes(), expected.as_bytes())
+    }
+
+    #[test]
+    fn trimmed() {
+        assert!(matches(Compare::Trimmed, "1 2\n", "1 2"));
+        assert!(matches(Compare::Trimmed, "\n 1 2 \r\n", "1 2\n"));
+        assert!(matches(Compare::Trimmed, "", ""));
+        assert!(matches(Compare::Trimmed, "\n", ""));
+        assert!(!matches(Compare::Trimmed, "", "1"));
+        assert!(!matches(Compare::Trimmed, "1  2", "1 2"));
+        assert!(!matches(Compare::Trimmed, "1\r\n2", "1\n2"));
+    }
+
+    #[test]
+    fn exact() {
+        assert!(matches(Compare::Exact, "1 2\n", "1 2\n"));
+        assert!(matches(Compare::Exact, "", ""));
+        assert!(!matches(Compare::Exact, "1 2", "1 2\n"));
+        assert!(!matches(Compare::Exact, "1 2\r\n", "1 2\n"));
+        assert!(!matches(Compare::Exact, "", "\n"));
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(matches(Compare::Tokens, "1\n2 ", "1 2"));
+        assert!(matches(Compare::Tokens, "1\r\n2\r\n", "1\n2\n"));
+        assert!(matches(Compare::Tokens, " \n", ""));
+        assert!(!matches(Compare::Tokens, "", "1"));
+        assert!(!matches(Compare::Tokens, "1 2", "1"));
+        assert!(!matches(Compare::Tokens, "12", "1 2"));
+    }
+
+    #[test]
+    fn lines() {
+        assert!(matches(Compare::Lines, "1 2 \n3\t\n", "1 2\n3"));
+        assert!(matches(Compare::Lines, "1\r\n2\r\n", "1\n2\n"));
+        assert!(matches(Compare::Lines, "1\n2\n\n\n", "1\n2"));
+        assert!(matches(Compare::Lines, "", "\n\n"));
+        assert!(!matches(Compare::Lines, "", "1"));
+        assert!(!matches(Compare::Lines, " 1\n2", "1\n2"));
+        assert!(!matches(Compare::Lines, "1\n\n2", "1\n2"));
+        assert!(!matches(Compare::Lines, "1 2", "1\n2"));
+    }
+
+    #[test]
+    fn float() {
+        let absolute = Compare::Float {
+            absolute: 1e-6,
+            relative: 0.0,
+        };
+        assert!(matches(absolute, "1.0000005 2", "1 2.0"));
+        assert!(matches(absolute, "-0.0000001", "0"));
+        assert!(!matches(absolute, "1.00001", "1"));
+        assert!(!matches(absolute, "1000000.5", "1000000"));
+
+        let relative = Compare::Float {
+            absolute: 0.0,
+            relative: 1e-6,
+        };
+        assert!(matches(relative, "1000000.5", "1000000"));
+        assert!(matches(relative, "-1000000.5", "-1000000"));
+        assert!(!matches(relative, "1.00001", "1"));
+        assert!(!matches(relative, "0.0000001", "0"));
+
+        let exact = Compare::Float {
+            absolute: 0.0,
+            relative: 0.0,
+        };
+        assert!(matches(exact, "1.0\r\n", "1"));
+        assert!(matches(exact, "1e2", "100"));
+        assert!(!matches(exact, "1.0000001", "1"));
+    }
+
+    #[test]
+    fn float_non_finite() {
+        let compare = Compare::Float {
+            absolute: f64::INFINITY,
+            relative: f64::INFINITY,
+        };
+        // Only accepted as they are, however lax the tolerance
+        assert!(matches(compare, "nan inf -inf", "nan inf -inf"));
+        assert!(!matches(compare, "NaN", "nan"));
+        assert!(!matches(compare, "nan", "1"));
+        assert!(!matches(compare, "1", "nan"));
+        assert!(!matches(compare, "inf", "1e308"));
+        assert!(!matches(compare, "1e309", "inf"));
+        assert!(matches(compare, "1e308", "-1e308"));
+    }
+
+    #[test]
+    fn float_tokens() {
+        let compare = Compare::Float {
+            absolute: 1e-6,
+            relative: 0.0,
+        };
+        assert!(matches(compare, "YES 1.0000001\n", "YES 1"));
+        assert!(!matches(compare, "yes 1", "YES 1"));
+        assert!(!matches(compare, "1 1", "1"));
+        assert!(matches(compare, "", "\n"));
+        assert!(!matches(compare, "", "0"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert!(matches(Compare::CaseInsensitive, "Yes nO\r\n", "YES no"));
+        assert!(matches(Compare::CaseInsensitive, "", " "));
+        assert!(!matches(Compare::CaseInsensitive, "yes", "yes yes"));
+        assert!(!matches(Compare::CaseInsensitive, "yes", "ye s"));
+        // Only ASCII letters are folded
+        assert!(!matches(Compare::CaseInsensitive, "É", "é"));
+    }
+
+    #[test]
+    fn unordered_lines() {
+        assert!(matches(Compare::UnorderedLines, "b\na \r\n", "a\nb"));
+        assert!(matches(Compare::UnorderedLines, "a\n\nb\n\n", "b\na"));
+        assert!(matches(Compare::UnorderedLines, "a\nb\na", "b\na\na\n"));
+        assert!(matches(Compare::UnorderedLines, "", "\n"));
+        assert!(!matches(Compare::UnorderedLines, "a\na\nb", "a\nb\nb"));
+        assert!(!matches(Compare::UnorderedLines, "a\nb", "a\na\nb"));
+        assert!(!matches(Compare::UnorderedLines, "a b", "b a"));
+        assert!(!matches(Compare::UnorderedLines, " a\nb", "a\nb"));
+    }
+}
@@ -1,7 +1,23 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+use tokio::fs::{self, File};
 
-use crate::sandbox::ResourceLimits;
+use crate::{
+    compare::Compare,
+    sandbox::{
+        seccomp::{self, PolicyError},
+        MemoryMode, ResourceLimits, SeccompPolicy, SeccompProgram, StackLimit, Stdin,
+        TimeMeasurement,
+    },
+};
 
 // NOTE: not all fields are used by the judge server, but are included to generate a JSON Schema
 
@@ -18,13 +34,63 @@ pub struct Contest {
     pub config: Config,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct Task {
     pub name: String,
     pub difficulty: Difficulty,
-    pub answer: Option<String>,
+    /// Makes the task accept answers, which are compared to this answer
+    pub answer: Option<Answer>,
+    /// How submitted answers are compared to `answer`
+    #[serde(default)]
+    pub answer_compare: Compare,
     pub page: String,
     pub subtasks: Vec<Subtask>,
+    /// Makes the task interactive: instead of comparing its output, each test connects the
+    /// submission to this interactor
+    pub interactor: Option<Interactor>,
+    /// Judges outputs with this checker instead of comparing them to the expected output, for
+    /// tasks with several valid answers
+    pub checker: Option<Checker>,
+    /// How submissions read the input and write their output, standard I/O by default
+    #[serde(default)]
+    pub io: Io,
+    /// How outputs are compared to the expected output, unless the task has a checker
+    #[serde(default)]
+    pub compare: Compare,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Answer {
+    One(String),
+    /// Any of these answers is accepted
+    Any(Vec<String>),
+}
+
+impl Answer {
+    /// Whether `answer` matches any accepted answer according to `compare`.
+    pub fn accepts(&self, answer: &str, compare: Compare) -> bool {
+        let accepted = match self {
+            Answer::One(accepted) => std::slice::from_ref(accepted),
+            Answer::Any(accepted) => accepted.as_slice(),
+        };
+
+        accepted
+            .iter()
+            .any(|accepted| compare.matches(answer.as_bytes(), accepted.as_bytes()))
+    }
+}
+
+#[derive(Debug, Default, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Io {
+    #[default]
+    Stdio,
+    /// Files in the submission's directory, such as `input.txt` and `output.txt`. The output file
+    /// is the only one submissions may write, and tests are run one at a time. Not supported by
+    /// interactive tasks.
+    File { input: String, output: String },
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
@@ -34,15 +100,104 @@ pub enum Difficulty {
     Hard,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
 pub struct Subtask {
+    #[serde(default)]
     pub tests: Vec<Test>,
+    /// Pattern of input files relative to the contest file, such as `tests/1-*.in`, whose expected
+    /// outputs are the files with the same name and the `.out` extension. Each match is added as a
+    /// test after those listed, in alphabetical order.
+    pub glob: Option<String>,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
 pub struct Test {
-    pub input: String,
-    pub output: String,
+    pub input: TestData,
+    pub output: TestData,
+    /// Overrides how the task compares the output of this test
+    pub compare: Option<Compare>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum TestData {
+    Inline(String),
+    /// File relative to the contest file, which is only read when the test is judged
+    File {
+        file: PathBuf,
+    },
+}
+
+impl TestData {
+    /// Reads the whole data, which is borrowed if it's inline.
+    pub async fn read(&self) -> io::Result<Cow<'_, [u8]>> {
+        match self {
+            TestData::Inline(data) => Ok(Cow::Borrowed(data.as_bytes())),
+            TestData::File { file } => fs::read(file).await.map(Cow::Owned),
+        }
+    }
+
+    /// Writes the data to a new file at `path`, streaming it from its own file if it has one.
+    pub async fn write(&self, path: &Path) -> io::Result<()> {
+        match self {
+            TestData::Inline(data) => fs::write(path, data).await,
+            TestData::File { file } => {
+                // Copied by hand so that the new file doesn't inherit the permissions of the original
+                let mut src = File::open(file).await?;
+                let mut dst = File::create(path).await?;
+                tokio::io::copy(&mut src, &mut dst).await.map(|_| ())
+            }
+        }
+    }
+
+    /// The data as a process's stdin, read as it's consumed if it's in a file.
+    pub fn stdin(&self) -> Stdin<'_> {
+        match self {
+            TestData::Inline(data) => Stdin::Bytes(data.as_bytes()),
+            TestData::File { file } => Stdin::File(file),
+        }
+    }
+}
+
+/// An author-supplied program which talks to the submission, its stdout feeding the submission's
+/// stdin and the other way around.
+///
+/// It runs in a directory of its own containing the test's `input` and expected `output`, and
+/// decides the verdict and any partial score only through its exit code, like a [`Checker`], but
+/// Accepted becomes Runtime Error if the submission failed. Exceeding its wall-clock limit is
+/// blamed on the submission being idle, and any other outcome is an error of the judge.
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Interactor {
+    /// Trusted command, which sees the host's filesystem read-only. A program given as a relative
+    /// path, such as `./interactor`, is relative to the contest file.
+    pub command: Vec<String>,
+    /// Overrides the contest's resource limits, which apply to the submission separately
+    pub resource_limits: Option<ResourceLimits>,
+}
+
+/// An author-supplied program which judges the output of each test, following the conventions
+/// of testlib so that existing checkers work unchanged.
+///
+/// It's compiled once when the contest is loaded, then run as `checker input output answer` with
+/// the paths of the test's input, the submission's output and the expected output. Its exit code
+/// decides the verdict: 0 for Accepted, 1 for Wrong Answer, 2, 4 or 8 for Presentation Error, 7 for
+/// the fraction of the test's score following `points` on its stderr, and 16 plus a percentage of
+/// the test's score. Any other outcome, such as 3 for a failed check, is an error of the judge.
+/// Its stderr is reported along with the verdict.
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Checker {
+    /// Source file, relative to the contest file
+    pub source: PathBuf,
+    /// Name of the contest language which compiles and runs the checker
+    pub language: String,
+    /// Overrides the contest's resource limits, the language's compile resource limits still
+    /// apply when compiling it
+    pub resource_limits: Option<ResourceLimits>,
+    /// Absolute path of the directory the checker was compiled in
+    #[serde(skip)]
+    pub dir: PathBuf,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
@@ -58,20 +213,297 @@ pub struct Scoring {
 pub struct Config {
     pub skip_count: u8,
     pub resource_limits: ResourceLimits,
+    /// Resource limits of compilers, where the process limit defaults to 64 instead, since
+    /// compilers such as gcc run several programs
+    #[serde(
+        default = "default_compile_resource_limits",
+        deserialize_with = "deserialize_compile_resource_limits"
+    )]
+    pub compile_resource_limits: ResourceLimits,
     #[serde(alias = "language")]
     pub languages: Vec<Language>,
+    #[serde(default)]
+    pub seccomp_policies: BTreeMap<String, SeccompPolicy>,
+}
+
+impl Config {
+    pub fn language(&self, name: &str) -> Option<&Language> {
+        self.languages.iter().find(|language| language.name == name)
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
 pub struct Language {
     pub name: String,
     pub filename: String,
     pub compile: Option<Vec<String>>,
     pub run: Vec<String>,
+    /// Overrides the contest's compile resource limits, with the same default process limit
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_compile_resource_limits"
+    )]
+    pub compile_resource_limits: Option<ResourceLimits>,
+    /// Overrides the contest's process and thread limit, for runtimes or submissions which are
+    /// allowed to be multithreaded
+    pub processes: Option<u64>,
+    /// Name of the seccomp policy applied to submissions, defaults to the built-in `default`
+    pub seccomp_policy: Option<String>,
+    /// Absolute paths made visible read-only to submissions, such as the interpreter and shared
+    /// libraries. Nothing else on the host is visible besides the submission directory.
+    #[serde(default)]
+    pub toolchain: Vec<PathBuf>,
+    #[serde(skip)]
+    pub seccomp_program: SeccompProgram,
+}
+
+fn default_compile_resource_limits() -> ResourceLimits {
+    ResourceLimits {
+        cpu: 10,
+        cpu_tolerance: 0.0,
+        wall: Some(30),
+        memory: 1 << 30,
+        memory_tolerance: 0,
+        stdout: 1 << 20,
+        stderr: 1 << 20,
+        processes: default_compile_processes(),
+        memory_mode: MemoryMode::Cgroup,
+        stack: StackLimit::Bytes(8 << 20),
+        file_size: 1 << 30,
+        open_files: 1024,
+        core: 0,
+        time_measurement: TimeMeasurement::Cpu,
+    }
+}
+
+fn default_compile_processes() -> u64 {
+    64
+}
+
+/// [`ResourceLimits`] with the default process limit of compilers.
+#[derive(Deserialize)]
+struct CompileResourceLimits {
+    #[serde(default = "default_compile_processes")]
+    processes: u64,
+    #[serde(flatten)]
+    resource_limits: ResourceLimits,
+}
+
+impl From<CompileResourceLimits> for ResourceLimits {
+    fn from(limits: CompileResourceLimits) -> Self {
+        ResourceLimits {
+            processes: limits.processes,
+            ..limits.resource_limits
+        }
+    }
+}
+
+fn deserialize_compile_resource_limits<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ResourceLimits, D::Error> {
+    CompileResourceLimits::deserialize(deserializer).map(ResourceLimits::from)
+}
+
+fn deserialize_optional_compile_resource_limits<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ResourceLimits>, D::Error> {
+    Option::<CompileResourceLimits>::deserialize(deserializer)
+        .map(|limits| limits.map(ResourceLimits::from))
+}
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("failed to parse contest: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error(transparent)]
+    Policy(#[from] PolicyError),
+    #[error("language {0} uses unknown seccomp policy `{1}`")]
+    UnknownPolicy(String, String),
+    #[error("task {0} uses `{1}` for file I/O, which isn't a plain file name")]
+    IoFile(String, String),
+    #[error("task {0} reads its input from and writes its output to the same file `{1}`")]
+    SameIoFile(String, String),
+    #[error("task {0} is interactive, which requires standard I/O")]
+    InteractiveFileIo(String),
+    #[error("task {0} uses unknown language `{1}` for its checker")]
+    UnknownCheckerLanguage(String, String),
+    #[error("task {0} is interactive, so its interactor already judges the output")]
+    InteractiveChecker(String),
+    #[error("task {0} has invalid test glob: {1}")]
+    Glob(String, #[source] glob::PatternError),
+    #[error("task {0} has test glob `{1}`, which matches no files")]
+    EmptyGlob(String, String),
+    #[error("task {0} uses test file {1}, which doesn't exist")]
+    TestFile(String, PathBuf),
+    #[error("task {0} uses checker source {1}, which doesn't exist")]
+    CheckerSource(String, PathBuf),
+    #[error("task {0} uses interactor {1}, which doesn't exist")]
+    Interactor(String, PathBuf),
 }
 
 impl Contest {
-    pub fn load(s: &str) -> Result<Self, serde_json::Error> {
-        serde_json::from_str(s)
+    /// Loads a contest from `s`, whose test files, checker sources and interactors are relative to
+    /// the directory `dir`.
+    pub fn load(s: &str, dir: &Path) -> Result<Self, LoadError> {
+        let mut contest: Contest = serde_json::from_str(s)?;
+
+        let programs = seccomp::compile(&contest.config.seccomp_policies)?;
+        for language in &mut contest.config.languages {
+            if let Some(policy) = &language.seccomp_policy {
+                language.seccomp_program = programs.get(policy).cloned().ok_or_else(|| {
+                    LoadError::UnknownPolicy(language.name.clone(), policy.clone())
+                })?;
+            }
+        }
+
+        for task in &mut contest.tasks {
+            for subtask in &mut task.subtasks {
+                for test in &mut subtask.tests {
+                    for data in [&mut test.input, &mut test.output] {
+                        if let TestData::File { file } = data {
+                            *file = dir.join(&*file);
+                            if !file.is_file() {
+                                return Err(LoadError::TestFile(task.name.clone(), file.clone()));
+                            }
+                        }
+                    }
+                }
+
+                if let Some(pattern) = &subtask.glob {
+                    let tests = glob_tests(&task.name, dir, pattern)?;
+                    subtask.tests.extend(tests);
+                }
+            }
+
+            if let Some(interactor) = &mut task.interactor {
+                // Run in a directory of its own, so it can't be given a relative path
+                if let Some(program) = interactor.command.first_mut() {
+                    if program.contains('/') && Path::new(program).is_relative() {
+                        let path = dir.join(&*program);
+                        *program = std::fs::canonicalize(&path)
+                            .ok()
+                            .and_then(|path| path.into_os_string().into_string().ok())
+                            .ok_or_else(|| LoadError::Interactor(task.name.clone(), path))?;
+                    }
+                }
+            }
+
+            if let Some(checker) = &mut task.checker {
+                checker.source = dir.join(&checker.source);
+                if !checker.source.is_file() {
+                    return Err(LoadError::CheckerSource(
+                        task.name.clone(),
+                        checker.source.clone(),
+                    ));
+                }
+
+                if task.interactor.is_some() {
+                    return Err(LoadError::InteractiveChecker(task.name.clone()));
+                }
+
+                if contest.config.language(&checker.language).is_none() {
+                    return Err(LoadError::UnknownCheckerLanguage(
+                        task.name.clone(),
+                        checker.language.clone(),
+                    ));
+                }
+            }
+
+            if let Io::File { input, output } = &task.io {
+                if task.interactor.is_some() {
+                    return Err(LoadError::InteractiveFileIo(task.name.clone()));
+                }
+
+                for file in [input, output] {
+                    if !is_file_name(file) {
+                        return Err(LoadError::IoFile(task.name.clone(), file.clone()));
+                    }
+                }
+
+                if input == output {
+                    return Err(LoadError::SameIoFile(task.name.clone(), input.clone()));
+                }
+            }
+        }
+
+        Ok(contest)
+    }
+}
+
+/// Tests for the input files matching `pattern` in `dir`, in alphabetical order.
+fn glob_tests(task: &str, dir: &Path, pattern: &str) -> Result<Vec<Test>, LoadError> {
+    let escaped = Path::new(&glob::Pattern::escape(&dir.to_string_lossy())).join(pattern);
+    let paths =
+        glob::glob(&escaped.to_string_lossy()).map_err(|e| LoadError::Glob(task.to_owned(), e))?;
+
+    let mut tests = vec![];
+    // Unreadable directories can't hold tests anyway
+    for input in paths.filter_map(Result::ok).filter(|path| path.is_file()) {
+        let output = input.with_extension("out");
+        if !output.is_file() {
+            return Err(LoadError::TestFile(task.to_owned(), output));
+        }
+
+        tests.push(Test {
+            input: TestData::File { file: input },
+            output: TestData::File { file: output },
+            compare: None,
+        });
+    }
+
+    if tests.is_empty() {
+        return Err(LoadError::EmptyGlob(task.to_owned(), pattern.to_owned()));
+    }
+
+    Ok(tests)
+}
+
+/// Whether `name` refers to a file directly inside the submission's directory.
+fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn defaults_compile_processes() {
+        let config: Config = serde_json::from_value(json!({
+            "skip-count": 1,
+            "resource-limits": { "cpu": 1, "memory": 1 << 20 },
+            "compile-resource-limits": { "cpu": 5, "memory": 1 << 30 },
+            "languages": [
+                {
+                    "name": "c",
+                    "filename": "main.c",
+                    "run": ["./main"],
+                    "compile-resource-limits": { "cpu": 20, "memory": 1 << 30 },
+                },
+                {
+                    "name": "c-serial",
+                    "filename": "main.c",
+                    "run": ["./main"],
+                    "compile-resource-limits": { "cpu": 20, "memory": 1 << 30, "processes": 2 },
+                },
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(config.resource_limits.processes, 1);
+        assert_eq!(config.compile_resource_limits.cpu, 5);
+        assert_eq!(config.compile_resource_limits.processes, 64);
+
+        let limits = config.languages[0].compile_resource_limits.unwrap();
+        assert_eq!((limits.cpu, limits.processes), (20, 64));
+        let limits = config.languages[1].compile_resource_limits.unwrap();
+        assert_eq!((limits.cpu, limits.processes), (20, 2));
     }
 }
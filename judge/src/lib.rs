@@ -2,6 +2,7 @@ use ahash::AHashMap;
 use contest::Contest;
 use once_cell::sync::OnceCell;
 
+pub mod compare;
 pub mod contest;
 pub mod sandbox;
 pub mod submit;
@@ -1,44 +1,258 @@
 use std::{
-    path::Path,
-    process::{ExitStatus, Stdio},
+    env, fmt,
+    future::Future,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::ExitStatus,
     str,
+    time::{Duration, Instant},
 };
 
-pub use resource::{ResourceLimits, ResourceUsage};
+pub use fake::Fake;
+pub use namespaced::Namespaced;
+pub use resource::{MemoryMode, ResourceLimits, ResourceUsage, StackLimit, TimeMeasurement};
+pub use rlimit::Rlimit;
+pub use seccomp::{SeccompPolicy, SeccompProgram};
+pub use slot::{Slot, SlotPool};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, Error, ErrorKind},
-    process::Command,
+    fs::File,
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error, ErrorKind},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout},
 };
+pub use trace::Syscall;
 
+mod cgroup;
+mod cpu;
+mod fake;
+mod filesystem;
+mod landlock;
+mod namespace;
+mod namespaced;
+mod perf;
 mod resource;
-mod seccomp;
+mod rlimit;
+pub mod seccomp;
+mod slot;
+mod trace;
+mod zygote;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// How long a terminated sandbox may take to exit before its supervisor is killed outright
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A way of running commands with limited resources.
+pub trait Sandbox: fmt::Debug + Send + Sync {
+    /// Runs `command` in `dir` as the user of `slot`, connecting it to `streams` and enforcing
+    /// `profile`.
+    fn run<'a>(
+        &'a self,
+        dir: &'a Path,
+        command: &'a [String],
+        streams: Streams<'a>,
+        profile: Profile,
+        slot: &'a Slot<'_>,
+    ) -> BoxFuture<'a, Result<Output, Error>>;
+}
+
+/// Where a sandboxed process reads its stdin from, and where its stdout and stderr go.
+///
+/// Stdin is fed while stdout and stderr are drained, so a process may write any amount of
+/// output before consuming its input.
+#[derive(Debug)]
+pub struct Streams<'a> {
+    pub stdin: Stdin<'a>,
+    pub stdout: Sink<'a>,
+    pub stderr: Sink<'a>,
+}
+
+impl<'a> Streams<'a> {
+    /// Feeds `stdin`, capturing stdout and stderr in the [`Output`].
+    pub fn new(stdin: impl Into<Stdin<'a>>) -> Self {
+        Streams {
+            stdin: stdin.into(),
+            stdout: Sink::Capture,
+            stderr: Sink::Capture,
+        }
+    }
+}
+
+pub enum Stdin<'a> {
+    /// Input which is already in memory
+    Bytes(&'a [u8]),
+    /// A file, read as the process consumes it
+    File(&'a Path),
+    /// Any other source, read as the process consumes it
+    Reader(Box<dyn AsyncRead + Send + Unpin + 'a>),
+}
+
+impl<'a> From<&'a [u8]> for Stdin<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        Stdin::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a Path> for Stdin<'a> {
+    fn from(path: &'a Path) -> Self {
+        Stdin::File(path)
+    }
+}
+
+impl fmt::Debug for Stdin<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stdin::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Stdin::File(path) => f.debug_tuple("File").field(path).finish(),
+            Stdin::Reader(_) => f.debug_tuple("Reader").finish_non_exhaustive(),
+        }
+    }
+}
+
+pub enum Sink<'a> {
+    /// Kept in memory, as [`Output::stdout`] or [`Output::stderr`]
+    Capture,
+    /// Written to a file, which is created or truncated, leaving the [`Output`] empty
+    File(&'a Path),
+    /// Written to any other destination as it's produced, leaving the [`Output`] empty. Once it
+    /// stops accepting writes, the rest of the output is discarded.
+    Writer(Box<dyn AsyncWrite + Send + Unpin + 'a>),
+}
+
+impl fmt::Debug for Sink<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sink::Capture => f.write_str("Capture"),
+            Sink::File(path) => f.debug_tuple("File").field(path).finish(),
+            Sink::Writer(_) => f.debug_tuple("Writer").finish_non_exhaustive(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Profile {
-    Compile,
-    Run(ResourceLimits),
+    /// A submission's compiler, which is trusted but mustn't see the judge's own data, since its
+    /// errors are shown to the contestant
+    Compile(ResourceLimits),
+    /// A checker being compiled or run, which is trusted with everything a compiler is, along with
+    /// the contest's files
+    Check(ResourceLimits),
+    /// A submission, along with the toolchain paths it may read and the only file in its working
+    /// directory it may write, if any
+    Run(
+        ResourceLimits,
+        SeccompProgram,
+        Vec<PathBuf>,
+        Option<PathBuf>,
+    ),
+    /// A trusted interactor, which is run like a checker but alongside the submission it talks
+    /// to, so it doesn't claim a CPU of its own
+    Interact(ResourceLimits),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl Profile {
+    pub fn resource_limits(&self) -> ResourceLimits {
+        match self {
+            Profile::Compile(resource_limits)
+            | Profile::Check(resource_limits)
+            | Profile::Run(resource_limits, ..)
+            | Profile::Interact(resource_limits) => *resource_limits,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Output {
     exit_status: ExitStatus,
     stdout: Vec<u8>,
     stderr: Vec<u8>,
     resource_usage: ResourceUsage,
+    timed_out: bool,
+    output_limit_exceeded: bool,
+    memory_limit_exceeded: bool,
+    process_limit_exceeded: bool,
+    stack_limit_exceeded: bool,
+    file_size_limit_exceeded: bool,
+    open_file_limit_exceeded: bool,
+    security_violation: Option<Syscall>,
+    instructions: Option<u64>,
 }
 
 impl Output {
-    pub fn new(
-        exit_status: ExitStatus,
-        stdout: Vec<u8>,
-        stderr: Vec<u8>,
-        resource_usage: ResourceUsage,
-    ) -> Self {
-        Self {
+    /// An output with nothing exceeded, mainly for [`Fake`] sandboxes.
+    pub fn new(exit_status: ExitStatus, stdout: Vec<u8>, stderr: Vec<u8>) -> Self {
+        Output {
             exit_status,
             stdout,
             stderr,
+            ..Output::default()
+        }
+    }
+
+    pub fn with_resource_usage(self, resource_usage: ResourceUsage) -> Self {
+        Output {
             resource_usage,
+            ..self
+        }
+    }
+
+    pub fn with_timed_out(self, timed_out: bool) -> Self {
+        Output { timed_out, ..self }
+    }
+
+    pub fn with_output_limit_exceeded(self, output_limit_exceeded: bool) -> Self {
+        Output {
+            output_limit_exceeded,
+            ..self
+        }
+    }
+
+    pub fn with_memory_limit_exceeded(self, memory_limit_exceeded: bool) -> Self {
+        Output {
+            memory_limit_exceeded,
+            ..self
+        }
+    }
+
+    pub fn with_process_limit_exceeded(self, process_limit_exceeded: bool) -> Self {
+        Output {
+            process_limit_exceeded,
+            ..self
+        }
+    }
+
+    pub fn with_stack_limit_exceeded(self, stack_limit_exceeded: bool) -> Self {
+        Output {
+            stack_limit_exceeded,
+            ..self
+        }
+    }
+
+    pub fn with_file_size_limit_exceeded(self, file_size_limit_exceeded: bool) -> Self {
+        Output {
+            file_size_limit_exceeded,
+            ..self
+        }
+    }
+
+    pub fn with_open_file_limit_exceeded(self, open_file_limit_exceeded: bool) -> Self {
+        Output {
+            open_file_limit_exceeded,
+            ..self
+        }
+    }
+
+    pub fn with_security_violation(self, security_violation: Option<Syscall>) -> Self {
+        Output {
+            security_violation,
+            ..self
+        }
+    }
+
+    pub fn with_instructions(self, instructions: Option<u64>) -> Self {
+        Output {
+            instructions,
+            ..self
         }
     }
 
@@ -65,71 +279,296 @@ impl Output {
     pub fn resource_usage(&self) -> ResourceUsage {
         self.resource_usage
     }
+
+    /// Whether the process was killed for exceeding the wall-clock limit
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Whether the process was killed for exceeding the stdout or stderr size limit
+    pub fn output_limit_exceeded(&self) -> bool {
+        self.output_limit_exceeded
+    }
+
+    /// Whether a process was killed for exceeding the memory limit, which is only detected when
+    /// the limit is enforced by a cgroup
+    pub fn memory_limit_exceeded(&self) -> bool {
+        self.memory_limit_exceeded
+    }
+
+    /// Whether creating a process or thread failed due to the process limit, which is only
+    /// detected when the run is placed in a cgroup
+    pub fn process_limit_exceeded(&self) -> bool {
+        self.process_limit_exceeded
+    }
+
+    /// Whether the process was killed by a segmentation fault with its stack close to the limit
+    pub fn stack_limit_exceeded(&self) -> bool {
+        self.stack_limit_exceeded
+    }
+
+    /// Whether a process tried to write a file past the file size limit
+    pub fn file_size_limit_exceeded(&self) -> bool {
+        self.file_size_limit_exceeded
+    }
+
+    /// Whether a process had as many file descriptors open as allowed when it exited, which is
+    /// only detected for submissions
+    pub fn open_file_limit_exceeded(&self) -> bool {
+        self.open_file_limit_exceeded
+    }
+
+    /// The syscall for which the process was killed by its seccomp policy
+    pub fn security_violation(&self) -> Option<Syscall> {
+        self.security_violation
+    }
+
+    /// The user-space instructions retired by the process and its children, which are only
+    /// counted for submissions timed by [`TimeMeasurement::Instructions`]
+    pub fn instructions(&self) -> Option<u64> {
+        self.instructions
+    }
 }
 
-pub async fn run(
-    dir: impl AsRef<Path>,
-    command: &[String],
-    stdin: &[u8],
-    profile: Profile,
-) -> Result<Output, Error> {
-    let dir = dir.as_ref();
-
-    let mut child = {
-        let (executable, args) = command
-            .split_first()
-            .ok_or_else(|| Error::new(ErrorKind::NotFound, "empty command"))?;
-
-        let mut cmd = Command::new(executable);
-        cmd.args(args)
-            .current_dir(dir)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        if let Profile::Run(resource_limits) = profile {
-            unsafe {
-                cmd.pre_exec(move || {
-                    resource_limits.set()?;
-
-                    seccomp::apply_filters().map_err(|e| {
-                        Error::new(ErrorKind::Other, format!("seccomp failed: {e}"))
-                    })?;
-
-                    Ok(())
-                });
-            }
+/// Selects the backend named by `SANDBOX_BACKEND`, `namespaced` by default, detecting its
+/// available features and logging any fallbacks.
+pub fn init() -> Box<dyn Sandbox> {
+    match env::var("SANDBOX_BACKEND").as_deref() {
+        Ok("rlimit") => Box::new(Rlimit::new()),
+        Ok("namespaced") | Err(_) => Box::new(Namespaced::new()),
+        Ok(backend) => {
+            tracing::error!("unknown SANDBOX_BACKEND {backend:?}, using namespaced");
+            Box::new(Namespaced::new())
         }
+    }
+}
 
-        cmd.spawn()?
-    };
+/// A spawned sandboxed process and its stdio pipes.
+struct Process<I, O, E> {
+    /// PID of the process which `terminate` and `reap` are given
+    pid: i32,
+    stdin: I,
+    stdout: O,
+    stderr: E,
+}
 
-    if let Err(e) = child.stdin.take().expect("no stdin").write_all(stdin).await {
-        tracing::error!("failed to write stdin: {e}");
+impl Process<ChildStdin, ChildStdout, ChildStderr> {
+    /// Takes the pipes of `child`, which must still be kept around until it's reaped.
+    fn from_child(child: &mut Child) -> Self {
+        Process {
+            pid: child.id().expect("child process has no PID") as i32,
+            stdin: child.stdin.take().expect("no stdin"),
+            stdout: child.stdout.take().expect("no stdout"),
+            stderr: child.stderr.take().expect("no stderr"),
+        }
     }
+}
+
+/// Waits for a spawned `process` while feeding and draining `streams`, enforcing the output and
+/// wall-clock limits with `terminate`.
+///
+/// `reap` waits for the process in a blocking task, and returns its exit status, resource usage
+/// and whatever limits the backend detected as exceeded.
+async fn wait(
+    process: Process<impl AsyncWrite + Unpin, impl AsyncRead + Unpin, impl AsyncRead + Unpin>,
+    streams: Streams<'_>,
+    resource_limits: ResourceLimits,
+    terminate: fn(i32) -> Result<(), Error>,
+    reap: impl FnOnce(i32) -> Result<Output, Error> + Send + 'static,
+) -> Result<Output, Error> {
+    let start = Instant::now();
 
-    let (stdout, stderr) = {
-        let (mut stdout, mut stderr) = (
-            child.stdout.take().expect("no stdout"),
-            child.stderr.take().expect("no stderr"),
+    let Process {
+        pid,
+        stdin: stdin_pipe,
+        stdout,
+        stderr,
+    } = process;
+
+    let wait = async move {
+        let (stdin, stdout, stderr) = tokio::join!(
+            write_stdin(stdin_pipe, streams.stdin, pid, terminate),
+            read_limited(
+                stdout,
+                streams.stdout,
+                resource_limits.stdout,
+                pid,
+                terminate
+            ),
+            read_limited(
+                stderr,
+                streams.stderr,
+                resource_limits.stderr,
+                pid,
+                terminate
+            ),
         );
-        let (mut stdout_buf, mut stderr_buf) = (Vec::new(), Vec::new());
 
-        if let Err(e) = stdout.read_to_end(&mut stdout_buf).await {
-            tracing::error!("failed to read stdout: {e}");
+        // Reaped even if a stream failed, which has terminated the process already
+        let output = tokio::task::spawn_blocking(move || reap(pid)).await??;
+        stdin?;
+        let (stdout, stdout_exceeded) = stdout?;
+        let (stderr, stderr_exceeded) = stderr?;
+
+        Ok::<_, Error>(Output {
+            stdout,
+            stderr,
+            output_limit_exceeded: stdout_exceeded || stderr_exceeded,
+            ..output
+        })
+    };
+    tokio::pin!(wait);
+
+    let mut output = match tokio::time::timeout(resource_limits.wall_time(), &mut wait).await {
+        Ok(result) => result?,
+        Err(_) => {
+            tracing::trace!("wall-clock limit exceeded, terminating");
+            if let Err(e) = terminate(pid) {
+                tracing::error!("failed to terminate sandbox: {e}");
+            }
+
+            let output = match tokio::time::timeout(TERMINATE_TIMEOUT, &mut wait).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    tracing::error!("sandbox still running after being terminated, killing it");
+                    // The rest of the sandbox dies along with its supervisor
+                    if let Err(e) = cvt(unsafe { libc::kill(pid, libc::SIGKILL) }) {
+                        tracing::error!("failed to kill sandbox: {e}");
+                    }
+                    wait.await?
+                }
+            };
+            Output {
+                timed_out: true,
+                ..output
+            }
         }
+    };
+    output.resource_usage.wall_time = start.elapsed();
+    output.file_size_limit_exceeded |= output.exit_status.signal() == Some(libc::SIGXFSZ);
 
-        if let Err(e) = stderr.read_to_end(&mut stderr_buf).await {
-            tracing::error!("failed to read stderr: {e}");
+    Ok(output)
+}
+
+fn cvt(result: libc::c_int) -> Result<libc::c_int, Error> {
+    if result < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Writes `stdin` to `pipe` and closes it, terminating the sandbox if `stdin` can't be read.
+async fn write_stdin(
+    mut pipe: impl AsyncWrite + Unpin,
+    stdin: Stdin<'_>,
+    pid: i32,
+    terminate: fn(i32) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let result = match stdin {
+        Stdin::Bytes(bytes) => pipe.write_all(bytes).await,
+        Stdin::File(path) => match File::open(path).await {
+            Ok(mut file) => io::copy(&mut file, &mut pipe).await.map(drop),
+            Err(e) => Err(e),
+        },
+        Stdin::Reader(mut reader) => io::copy(&mut reader, &mut pipe).await.map(drop),
+    };
+
+    match result {
+        Ok(()) => Ok(()),
+        // The process doesn't have to read all of its input
+        Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(()),
+        Err(e) => {
+            tracing::error!("failed to write stdin: {e}");
+            if let Err(e) = terminate(pid) {
+                tracing::error!("failed to terminate sandbox: {e}");
+            }
+            Err(e)
         }
+    }
+}
 
-        (stdout_buf, stderr_buf)
+/// Reads `pipe` to the end into `sink`, terminating the sandbox if it produces more than `limit`
+/// bytes or `sink` can't be written.
+///
+/// Returns the captured bytes, if any, and whether the limit was exceeded.
+async fn read_limited(
+    pipe: impl AsyncRead + Unpin,
+    sink: Sink<'_>,
+    limit: u64,
+    pid: i32,
+    terminate: fn(i32) -> Result<(), Error>,
+) -> Result<(Vec<u8>, bool), Error> {
+    let mut buf = Vec::new();
+
+    let result = match sink {
+        Sink::Capture => copy_limited(pipe, &mut buf, limit).await,
+        Sink::File(path) => match File::create(path).await {
+            Ok(mut file) => match copy_limited(pipe, &mut file, limit).await {
+                Ok(exceeded) => file.flush().await.map(|()| exceeded),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        },
+        Sink::Writer(mut writer) => match copy_limited(pipe, &mut writer, limit).await {
+            Ok(exceeded) => match writer.shutdown().await {
+                Ok(()) => Ok(exceeded),
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(exceeded),
+                Err(e) => Err(e),
+            },
+            // The process is left to notice that its output isn't read anymore
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => Ok(false),
+            Err(e) => Err(e),
+        },
     };
 
-    let (exit_status, resource_usage) = tokio::task::spawn_blocking(move || {
-        resource::wait4(child.id().expect("child process has no PID") as i32)
-    })
-    .await??;
+    match result {
+        Ok(exceeded) => {
+            if exceeded {
+                tracing::trace!("output limit exceeded, terminating");
+                if let Err(e) = terminate(pid) {
+                    tracing::error!("failed to terminate sandbox: {e}");
+                }
+            }
+            Ok((buf, exceeded))
+        }
+        Err(e) => {
+            tracing::error!("failed to write output: {e}");
+            if let Err(e) = terminate(pid) {
+                tracing::error!("failed to terminate sandbox: {e}");
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Copies at most `limit` bytes from `pipe` to `writer`, returning whether there were more.
+///
+/// Reading stops at the first error, as if `pipe` had ended.
+async fn copy_limited(
+    mut pipe: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    limit: u64,
+) -> Result<bool, Error> {
+    let mut chunk = vec![0; 64 * 1024];
+    let mut remaining = limit;
+
+    loop {
+        let len = match pipe.read(&mut chunk).await {
+            Ok(0) => return Ok(false),
+            Ok(len) => len,
+            Err(e) => {
+                tracing::error!("failed to read output: {e}");
+                return Ok(false);
+            }
+        };
 
-    Ok(Output::new(exit_status, stdout, stderr, resource_usage))
+        if len as u64 > remaining {
+            writer.write_all(&chunk[..remaining as usize]).await?;
+            return Ok(true);
+        }
+
+        writer.write_all(&chunk[..len]).await?;
+        remaining -= len as u64;
+    }
 }
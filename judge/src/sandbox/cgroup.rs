@@ -0,0 +1,257 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use uuid::Uuid;
+
+use super::{resource::MemoryMode, ResourceLimits, ResourceUsage};
+
+const CONTROLLERS: [&str; 3] = ["memory", "pids", "cpu"];
+/// Controllers which are enabled if they're delegated, but not required
+const OPTIONAL_CONTROLLERS: [&str; 1] = ["cpuset"];
+/// CPU bandwidth available to a run, as a quota and period in microseconds (one CPU)
+const CPU_MAX: &str = "100000 100000";
+
+static ROOT: Lazy<Option<PathBuf>> = Lazy::new(|| match detect() {
+    Ok(root) => {
+        tracing::info!("using cgroup v2 hierarchy at {}", root.display());
+        Some(root)
+    }
+    Err(e) => {
+        tracing::warn!("cgroups unavailable, falling back to rlimits: {e}");
+        None
+    }
+});
+
+/// Returns whether runs are placed into cgroups.
+pub fn available() -> bool {
+    ROOT.is_some()
+}
+
+fn detect() -> io::Result<PathBuf> {
+    let root = match env::var_os("CGROUP_ROOT") {
+        Some(root) => PathBuf::from(root),
+        None => own_cgroup()?,
+    };
+
+    let controllers = fs::read_to_string(root.join("cgroup.controllers"))?;
+    for controller in CONTROLLERS {
+        if !controllers.split_whitespace().any(|c| c == controller) {
+            return Err(io::Error::other(format!(
+                "{controller} controller is not delegated to {}",
+                root.display()
+            )));
+        }
+    }
+
+    // Controllers can't be enabled for the children of a cgroup which contains processes, so the
+    // judge moves itself into a leaf cgroup first
+    if !fs::read_to_string(root.join("cgroup.procs"))?
+        .trim()
+        .is_empty()
+    {
+        let leaf = root.join("judge");
+        if !leaf.is_dir() {
+            fs::create_dir(&leaf)?;
+        }
+        fs::write(leaf.join("cgroup.procs"), process::id().to_string())?;
+    }
+
+    let subtree_control = CONTROLLERS
+        .into_iter()
+        .chain(
+            OPTIONAL_CONTROLLERS
+                .into_iter()
+                .filter(|&controller| controllers.split_whitespace().any(|c| c == controller)),
+        )
+        .map(|c| format!("+{c}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    fs::write(root.join("cgroup.subtree_control"), subtree_control)?;
+
+    Ok(root)
+}
+
+fn own_cgroup() -> io::Result<PathBuf> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+    let path = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| io::Error::other("not running in a cgroup v2 hierarchy"))?;
+
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let mount_point = mountinfo
+        .lines()
+        .find_map(|line| {
+            let (fields, fs_fields) = line.split_once(" - ")?;
+            let fs_type = fs_fields.split_whitespace().next()?;
+            (fs_type == "cgroup2").then(|| fields.split_whitespace().nth(4))?
+        })
+        .ok_or_else(|| io::Error::other("cgroup2 filesystem is not mounted"))?;
+
+    Ok(Path::new(mount_point).join(path.trim_start_matches('/')))
+}
+
+/// A cgroup containing a single sandboxed run, removed when dropped.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl Cgroup {
+    /// Creates a cgroup enforcing `resource_limits` and confined to `cpu` if given, or returns
+    /// `None` if cgroups are unavailable.
+    pub fn create(resource_limits: ResourceLimits, cpu: Option<usize>) -> io::Result<Option<Self>> {
+        let Some(root) = ROOT.as_ref() else {
+            return Ok(None);
+        };
+
+        let path = root.join(format!("run-{}", Uuid::new_v4()));
+        fs::create_dir(&path)?;
+
+        // Opened ahead of time so that the child only has to write to it
+        let procs = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open(path.join("cgroup.procs"));
+        let cgroup = Cgroup {
+            procs: match procs {
+                Ok(procs) => procs,
+                Err(e) => {
+                    fs::remove_dir(&path).ok();
+                    return Err(e);
+                }
+            },
+            path,
+        };
+
+        if resource_limits.memory_mode == MemoryMode::Cgroup {
+            cgroup.write("memory.max", resource_limits.memory.to_string())?;
+        }
+        if cgroup.path.join("memory.swap.max").exists() {
+            cgroup.write("memory.swap.max", "0")?;
+        }
+        // The sandbox supervisor is also in the cgroup
+        cgroup.write("pids.max", (resource_limits.processes + 1).to_string())?;
+        cgroup.write("cpu.max", CPU_MAX)?;
+        if let Some(cpu) = cpu {
+            // Unlike the affinity set by the sandbox, this can't be undone by the submission
+            if cgroup.path.join("cpuset.cpus").exists() {
+                cgroup.write("cpuset.cpus", cpu.to_string())?;
+            }
+        }
+
+        Ok(Some(cgroup))
+    }
+
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// Replaces the measurements in `resource_usage` with the cgroup's accounting, which also
+    /// covers child processes and memory that `getrusage` doesn't see.
+    pub fn update_usage(&self, resource_usage: &mut ResourceUsage) -> io::Result<()> {
+        let cpu_stat = fs::read_to_string(self.path.join("cpu.stat"))?;
+        for line in cpu_stat.lines() {
+            match line.split_once(' ') {
+                Some(("user_usec", usec)) => {
+                    resource_usage.user_time = Duration::from_micros(parse(usec)?)
+                }
+                Some(("system_usec", usec)) => {
+                    resource_usage.sys_time = Duration::from_micros(parse(usec)?)
+                }
+                _ => {}
+            }
+        }
+
+        // memory.peak is only available since Linux 5.19
+        match fs::read_to_string(self.path.join("memory.peak")) {
+            Ok(peak) => resource_usage.memory = parse(&peak)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether creating a process or thread failed due to the process limit.
+    pub fn process_limit_exceeded(&self) -> io::Result<bool> {
+        let pids_events = fs::read_to_string(self.path.join("pids.events"))?;
+        for line in pids_events.lines() {
+            if let Some(("max", count)) = line.split_once(' ') {
+                return Ok(parse(count)? > 0);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns whether a process was killed by the OOM killer for exceeding the memory limit.
+    pub fn memory_limit_exceeded(&self) -> io::Result<bool> {
+        let memory_events = fs::read_to_string(self.path.join("memory.events"))?;
+        for line in memory_events.lines() {
+            if let Some(("oom_kill", count)) = line.split_once(' ') {
+                return Ok(parse(count)? > 0);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn write(&self, file: &str, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        fs::write(self.path.join(file), contents)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // cgroup.kill is only available since Linux 5.14, but the sandbox normally leaves the
+        // cgroup empty by the time it's dropped anyway
+        self.write("cgroup.kill", "1").ok();
+
+        for _ in 0..100 {
+            match fs::remove_dir(&self.path) {
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                Err(e) => {
+                    tracing::error!("failed to remove cgroup {}: {e}", self.path.display());
+                    return;
+                }
+                Ok(()) => return,
+            }
+        }
+
+        tracing::error!("timed out removing cgroup {}", self.path.display());
+    }
+}
+
+/// Moves the calling process into the cgroup whose `cgroup.procs` file is open as `procs_fd`.
+///
+/// # Safety
+///
+/// Must only be called from a `pre_exec` hook.
+pub unsafe fn join(procs_fd: RawFd) -> io::Result<()> {
+    if libc::write(procs_fd, b"0".as_ptr().cast(), 1) < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn parse(s: &str) -> io::Result<u64> {
+    s.trim()
+        .parse()
+        .map_err(|e| io::Error::other(format!("malformed cgroup file: {e}")))
+}
@@ -0,0 +1,176 @@
+use std::{collections::BTreeSet, env, fs, io, mem, sync::Mutex};
+
+use once_cell::sync::Lazy;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::cvt;
+
+static CORES: Lazy<CorePool> = Lazy::new(|| {
+    let cores = match env::var("SANDBOX_CPUS") {
+        Ok(list) => configured_cores(&list),
+        Err(_) => default_cores(),
+    };
+
+    match cores {
+        Ok(cores) if !cores.is_empty() => {
+            tracing::info!("pinning each sandbox to one of CPUs {cores:?}");
+            CorePool::new(cores)
+        }
+        Ok(_) => {
+            tracing::warn!("no CPUs to pin sandboxes to, running them unpinned");
+            CorePool::new(BTreeSet::new())
+        }
+        Err(e) => {
+            tracing::warn!("failed to determine CPUs, running sandboxes unpinned: {e}");
+            CorePool::new(BTreeSet::new())
+        }
+    }
+});
+
+/// Returns whether sandboxes are pinned to dedicated CPUs.
+pub fn available() -> bool {
+    !CORES.cores.is_empty()
+}
+
+/// Waits until a CPU is free and claims it, or returns `None` if sandboxes aren't pinned.
+pub async fn acquire() -> Option<Core> {
+    CORES.acquire().await
+}
+
+#[derive(Debug)]
+struct CorePool {
+    cores: BTreeSet<usize>,
+    free: Mutex<Vec<usize>>,
+    semaphore: Semaphore,
+}
+
+impl CorePool {
+    fn new(cores: BTreeSet<usize>) -> Self {
+        CorePool {
+            free: Mutex::new(cores.iter().rev().copied().collect()),
+            semaphore: Semaphore::new(cores.len()),
+            cores,
+        }
+    }
+
+    async fn acquire(&'static self) -> Option<Core> {
+        if self.cores.is_empty() {
+            return None;
+        }
+
+        let permit = self.semaphore.acquire().await.expect("semaphore closed");
+        let cpu = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("no free CPU despite permit");
+
+        Some(Core {
+            pool: self,
+            cpu,
+            _permit: permit,
+        })
+    }
+}
+
+/// A CPU claimed by a single sandboxed run, so that its timing isn't disturbed by other runs.
+#[derive(Debug)]
+pub struct Core {
+    pool: &'static CorePool,
+    cpu: usize,
+    _permit: SemaphorePermit<'static>,
+}
+
+impl Core {
+    pub fn cpu(&self) -> usize {
+        self.cpu
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        self.pool.free.lock().unwrap().push(self.cpu);
+    }
+}
+
+/// Restricts the calling process and its future children to `cpu`.
+///
+/// # Safety
+///
+/// Must only be called from a `pre_exec` hook.
+pub unsafe fn pin(cpu: usize) -> io::Result<()> {
+    let mut set: libc::cpu_set_t = mem::zeroed();
+    libc::CPU_SET(cpu, &mut set);
+    cvt(libc::sched_setaffinity(
+        0,
+        mem::size_of::<libc::cpu_set_t>(),
+        &set,
+    ))?;
+    Ok(())
+}
+
+/// CPUs in `list` that the judge may run on.
+fn configured_cores(list: &str) -> io::Result<BTreeSet<usize>> {
+    let allowed = allowed_cores()?;
+    let (cores, disallowed) = parse_list(list)?
+        .into_iter()
+        .partition::<BTreeSet<_>, _>(|cpu| allowed.contains(cpu));
+
+    if !disallowed.is_empty() {
+        tracing::warn!("ignoring CPUs {disallowed:?} which the judge may not run on");
+    }
+    Ok(cores)
+}
+
+/// CPUs that the judge may run on, keeping only the first hardware thread of each core since
+/// runs on SMT siblings would contend for the same execution units.
+fn default_cores() -> io::Result<BTreeSet<usize>> {
+    let allowed = allowed_cores()?;
+
+    Ok(allowed
+        .iter()
+        .copied()
+        .filter(|&cpu| {
+            let path = format!("/sys/devices/system/cpu/cpu{cpu}/topology/thread_siblings_list");
+            match fs::read_to_string(path).map(|list| parse_list(&list)) {
+                Ok(Ok(siblings)) => siblings
+                    .intersection(&allowed)
+                    .next()
+                    .is_none_or(|&first| first == cpu),
+                // Without a topology, every CPU is assumed to be a separate core
+                _ => true,
+            }
+        })
+        .collect())
+}
+
+fn allowed_cores() -> io::Result<BTreeSet<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    cvt(unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) })?;
+
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+/// Parses a CPU list such as `0-3,6`, as used by the kernel.
+fn parse_list(list: &str) -> io::Result<BTreeSet<usize>> {
+    let parse = |cpu: &str| match cpu.trim().parse::<usize>() {
+        Ok(cpu) if cpu < libc::CPU_SETSIZE as usize => Ok(cpu),
+        Ok(cpu) => Err(io::Error::other(format!("CPU {cpu} is out of range"))),
+        Err(e) => Err(io::Error::other(format!("invalid CPU list {list:?}: {e}"))),
+    };
+
+    let mut cpus = BTreeSet::new();
+    for range in list.split(',').filter(|range| !range.trim().is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(parse(start)?..=parse(end)?),
+            None => {
+                cpus.insert(parse(range)?);
+            }
+        }
+    }
+
+    Ok(cpus)
+}
@@ -0,0 +1,83 @@
+use std::{fmt, path::Path};
+
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt, Error, ErrorKind},
+};
+
+use super::{BoxFuture, Output, Profile, Sandbox, Sink, Slot, Stdin, Streams};
+
+type Handler = dyn Fn(&Path, &[String], &[u8], &Profile) -> Result<Output, Error> + Send + Sync;
+
+/// Answers each command in-process, without executing anything.
+///
+/// This is meant for testing the judging logic without spawning compilers or submissions. Stdin
+/// is read into memory before the handler is called, and its output is written to any sinks
+/// afterwards.
+pub struct Fake(Box<Handler>);
+
+impl Fake {
+    /// Answers each command with the output of `handler`, which is given the working directory,
+    /// the command, its stdin and its profile.
+    pub fn new(
+        handler: impl Fn(&Path, &[String], &[u8], &Profile) -> Result<Output, Error>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        Fake(Box::new(handler))
+    }
+}
+
+impl fmt::Debug for Fake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fake").finish_non_exhaustive()
+    }
+}
+
+impl Sandbox for Fake {
+    fn run<'a>(
+        &'a self,
+        dir: &'a Path,
+        command: &'a [String],
+        streams: Streams<'a>,
+        profile: Profile,
+        _slot: &'a Slot<'_>,
+    ) -> BoxFuture<'a, Result<Output, Error>> {
+        Box::pin(async move {
+            let stdin = match streams.stdin {
+                Stdin::Bytes(bytes) => bytes.to_vec(),
+                Stdin::File(path) => fs::read(path).await?,
+                Stdin::Reader(mut reader) => {
+                    let mut buf = Vec::new();
+                    reader.read_to_end(&mut buf).await?;
+                    buf
+                }
+            };
+
+            let mut output = (self.0)(dir, command, &stdin, &profile)?;
+            output.stdout = write_sink(streams.stdout, output.stdout).await?;
+            output.stderr = write_sink(streams.stderr, output.stderr).await?;
+
+            Ok(output)
+        })
+    }
+}
+
+/// Writes `bytes` to `sink`, returning what is left to capture.
+async fn write_sink(sink: Sink<'_>, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match sink {
+        Sink::Capture => Ok(bytes),
+        Sink::File(path) => {
+            fs::write(path, bytes).await?;
+            Ok(Vec::new())
+        }
+        Sink::Writer(mut writer) => {
+            match writer.write_all(&bytes).await {
+                Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e),
+                _ => {}
+            }
+            Ok(Vec::new())
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    ffi::{CStr, CString},
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use libc::c_int;
+
+use super::cvt;
+
+const TMPFS_OPTIONS: &CStr = c"size=256m,mode=1777";
+const ROOT_OPTIONS: &CStr = c"size=1m,mode=755";
+/// Where the minimal root is assembled before pivoting into it. Mounting over it only affects the
+/// sandbox's own mount namespace.
+const NEW_ROOT: &str = "/tmp";
+/// Device files bound into the minimal root
+pub const DEVICES: [&str; 4] = ["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom"];
+
+/// Filesystem view of a sandboxed process: everything is read-only except for its working
+/// directory and a private `/tmp`.
+///
+/// As with [`super::namespace::Namespaces`], paths are prepared in the parent.
+#[derive(Debug, Clone)]
+pub struct Filesystem {
+    workdir: CString,
+    /// `workdir` and its ancestors, outermost first
+    ancestors: Vec<CString>,
+    /// Directories covered by an empty tmpfs, apart from the working directory
+    hidden: Vec<CString>,
+    root: Option<Root>,
+}
+
+/// A minimal root containing only the working directory and read-only bind mounts, replacing the
+/// host's root filesystem.
+#[derive(Debug, Clone)]
+struct Root {
+    /// [`NEW_ROOT`]
+    path: CString,
+    /// Mount points to create under [`NEW_ROOT`] in order, and whether they're directories
+    mount_points: Vec<(CString, bool)>,
+    /// Read-only bind mounts, as source and target under [`NEW_ROOT`]
+    binds: Vec<(CString, CString)>,
+    workdir: CString,
+    tmp: CString,
+    proc: CString,
+}
+
+impl Filesystem {
+    pub fn new(workdir: &Path) -> io::Result<Self> {
+        let workdir = workdir.canonicalize()?;
+        let mut ancestors = workdir
+            .ancestors()
+            .map(|path| CString::new(path.as_os_str().as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        ancestors.reverse();
+
+        Ok(Filesystem {
+            workdir: CString::new(workdir.as_os_str().as_bytes())?,
+            ancestors,
+            hidden: Vec::new(),
+            root: None,
+        })
+    }
+
+    /// Hides the contents of `dir`, except for the working directory if it's inside of it.
+    pub fn hide(mut self, dir: &Path) -> io::Result<Self> {
+        let dir = dir.canonicalize()?;
+        // Hiding the root would leave nothing to run
+        if dir.parent().is_some() {
+            self.hidden.push(CString::new(dir.as_os_str().as_bytes())?);
+        }
+        Ok(self)
+    }
+
+    /// Like [`Filesystem::new`], but the host's root filesystem is replaced entirely. Only the
+    /// `toolchain` paths are visible, read-only and at the same locations, along with a few
+    /// device files and `/proc` if it can be mounted.
+    pub fn with_root(workdir: &Path, toolchain: &[PathBuf]) -> io::Result<Self> {
+        let mut filesystem = Filesystem::new(workdir)?;
+        let mut root = Root {
+            path: CString::new(NEW_ROOT)?,
+            mount_points: Vec::new(),
+            binds: Vec::new(),
+            workdir: CString::default(),
+            tmp: new_root(Path::new("/tmp"))?,
+            proc: new_root(Path::new("/proc"))?,
+        };
+
+        for path in DEVICES
+            .iter()
+            .map(Path::new)
+            .chain(toolchain.iter().map(PathBuf::as_path))
+        {
+            if !path.is_absolute() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("toolchain path {} is not absolute", path.display()),
+                ));
+            }
+
+            // Symlinks are followed, so that the target is mounted at the path of the link
+            let is_dir = fs::metadata(path)?.is_dir();
+            root.mount_point(path, is_dir)?;
+            root.binds
+                .push((CString::new(path.as_os_str().as_bytes())?, new_root(path)?));
+        }
+
+        let workdir = workdir.canonicalize()?;
+        root.mount_point(&workdir, true)?;
+        root.workdir = new_root(&workdir)?;
+
+        root.mount_point(Path::new("/proc"), true)?;
+
+        filesystem.root = Some(root);
+        Ok(filesystem)
+    }
+
+    /// Sets up the filesystem view of the calling process, which must already be inside its own
+    /// mount namespace.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called from a `pre_exec` hook or in a process forked from the judge.
+    pub unsafe fn enter(&self) -> io::Result<()> {
+        if let Some(root) = &self.root {
+            return root.enter(&self.workdir);
+        }
+
+        let workdir = self.workdir.as_ptr();
+
+        set_read_only(c"/", true, libc::AT_RECURSIVE)?;
+
+        mount_tmpfs(c"/tmp", TMPFS_OPTIONS)?;
+        for dir in &self.hidden {
+            mount_tmpfs(dir, ROOT_OPTIONS)?;
+        }
+
+        // The working directory may have just been hidden by a tmpfs, in which case its mount
+        // point is recreated there. The current directory still refers to the original.
+        for path in &self.ancestors {
+            mkdir(path)?;
+        }
+
+        cvt(libc::mount(
+            c".".as_ptr(),
+            workdir,
+            std::ptr::null(),
+            libc::MS_BIND,
+            std::ptr::null(),
+        ))?;
+        // Bind mounts inherit the read-only flag of their source, so it has to be cleared again
+        set_read_only(&self.workdir, false, 0)?;
+        for dir in &self.hidden {
+            set_read_only(dir, true, 0)?;
+        }
+
+        cvt(libc::chdir(workdir))?;
+
+        Ok(())
+    }
+}
+
+impl Root {
+    /// Adds `path` and its ancestors as mount points.
+    fn mount_point(&mut self, path: &Path, is_dir: bool) -> io::Result<()> {
+        // The root itself already exists
+        let mut ancestors = path
+            .ancestors()
+            .skip(1)
+            .filter(|ancestor| ancestor.parent().is_some())
+            .collect::<Vec<_>>();
+        ancestors.reverse();
+
+        for ancestor in ancestors {
+            self.mount_points.push((new_root(ancestor)?, true));
+        }
+        self.mount_points.push((new_root(path)?, is_dir));
+
+        Ok(())
+    }
+
+    unsafe fn enter(&self, workdir: &CStr) -> io::Result<()> {
+        // The working directory may be hidden by this, but the current directory still refers to
+        // the original
+        mount_tmpfs(&self.path, ROOT_OPTIONS)?;
+
+        // Mounted first, since other mount points may be inside of it
+        mkdir(&self.tmp)?;
+        mount_tmpfs(&self.tmp, TMPFS_OPTIONS)?;
+
+        for (path, is_dir) in &self.mount_points {
+            if *is_dir {
+                mkdir(path)?;
+            } else {
+                let fd = cvt(libc::open(
+                    path.as_ptr(),
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                    0o644,
+                ))?;
+                libc::close(fd);
+            }
+        }
+
+        for (source, target) in &self.binds {
+            cvt(libc::mount(
+                source.as_ptr(),
+                target.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                std::ptr::null(),
+            ))?;
+            set_read_only(target, true, libc::AT_RECURSIVE)?;
+        }
+
+        cvt(libc::mount(
+            c".".as_ptr(),
+            self.workdir.as_ptr(),
+            std::ptr::null(),
+            libc::MS_BIND,
+            std::ptr::null(),
+        ))?;
+
+        // Not permitted when parts of the host's /proc are hidden, as they are inside containers
+        libc::mount(
+            c"proc".as_ptr(),
+            self.proc.as_ptr(),
+            c"proc".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            std::ptr::null(),
+        );
+
+        // Stacks the old root on top of the new one, from where it can be detached
+        cvt(libc::chdir(self.path.as_ptr()))?;
+        cvt(libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as c_int)?;
+        cvt(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+
+        set_read_only(c"/", true, 0)?;
+        cvt(libc::chdir(workdir.as_ptr()))?;
+
+        Ok(())
+    }
+}
+
+/// `path` as it appears under [`NEW_ROOT`] before pivoting.
+fn new_root(path: &Path) -> io::Result<CString> {
+    let mut bytes = NEW_ROOT.as_bytes().to_vec();
+    bytes.extend_from_slice(path.as_os_str().as_bytes());
+    Ok(CString::new(bytes)?)
+}
+
+/// Creates a directory, unless it already exists.
+unsafe fn mkdir(path: &CStr) -> io::Result<()> {
+    if libc::mkdir(path.as_ptr(), 0o755) < 0 && *libc::__errno_location() != libc::EEXIST {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+unsafe fn mount_tmpfs(path: &CStr, options: &CStr) -> io::Result<()> {
+    cvt(libc::mount(
+        c"tmpfs".as_ptr(),
+        path.as_ptr(),
+        c"tmpfs".as_ptr(),
+        libc::MS_NOSUID | libc::MS_NODEV,
+        options.as_ptr().cast(),
+    ))?;
+    Ok(())
+}
+
+unsafe fn set_read_only(path: &CStr, read_only: bool, flags: c_int) -> io::Result<()> {
+    let mut attr: libc::mount_attr = std::mem::zeroed();
+    if read_only {
+        attr.attr_set = libc::MOUNT_ATTR_RDONLY;
+    } else {
+        attr.attr_clr = libc::MOUNT_ATTR_RDONLY;
+    }
+
+    let result = libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path.as_ptr(),
+        flags as libc::c_uint,
+        &attr as *const libc::mount_attr,
+        std::mem::size_of::<libc::mount_attr>(),
+    );
+
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
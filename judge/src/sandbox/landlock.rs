@@ -0,0 +1,170 @@
+use std::{
+    ffi::CStr,
+    io,
+    os::fd::{OwnedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use ::landlock::{
+    path_beneath_rules, Access, AccessFs, BitFlags, Ruleset, RulesetAttr, RulesetCreatedAttr,
+    RulesetError, ABI,
+};
+use libc::c_int;
+use once_cell::sync::Lazy;
+
+use super::{cvt, filesystem::DEVICES};
+
+/// `landlock_create_ruleset` flag which queries the ABI version instead
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1;
+/// `landlock_add_rule` rule type of [`PathBeneathAttr`]
+const LANDLOCK_RULE_PATH_BENEATH: c_int = 1;
+
+/// `struct landlock_path_beneath_attr`
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: RawFd,
+}
+
+static VERSION: Lazy<c_int> = Lazy::new(|| {
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<libc::c_void>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    } as c_int;
+
+    match version {
+        ..=0 => tracing::warn!(
+            "Landlock unavailable, submissions are only confined by mount namespaces, if the \
+             sandbox uses them: {}",
+            io::Error::last_os_error()
+        ),
+        1 => tracing::warn!(
+            "using Landlock ABI v1, without restricting links and renames across directories \
+             or truncation"
+        ),
+        2 => tracing::warn!("using Landlock ABI v2, without restricting truncation"),
+        version => tracing::info!("using Landlock ABI v{version}"),
+    }
+
+    version
+});
+
+/// Returns whether submissions are confined with Landlock.
+pub fn available() -> bool {
+    *VERSION > 0
+}
+
+fn abi() -> ABI {
+    match *VERSION {
+        ..=0 => ABI::Unsupported,
+        1 => ABI::V1,
+        2 => ABI::V2,
+        // Newer ABIs only add access rights which aren't about files
+        _ => ABI::V3,
+    }
+}
+
+/// Creates a ruleset which only allows reading and executing `toolchain`, using the device files
+/// in the minimal root, and full access to `workdir`, or `None` if Landlock is unavailable.
+///
+/// If an `output_file` in `workdir` is given, `workdir` is only readable and that file is the
+/// only one which can be written. It must already exist, since it can't be created.
+///
+/// It's created in the parent, so that the sandboxed process only has to restrict itself. Rules
+/// follow inodes rather than paths, so they still apply once the paths are bound into the
+/// minimal root. Its private `/tmp` and `/proc` don't exist yet, and are allowed by
+/// [`allow_private_mounts`] instead.
+pub fn ruleset(
+    workdir: &Path,
+    toolchain: &[PathBuf],
+    output_file: Option<&Path>,
+) -> io::Result<Option<OwnedFd>> {
+    if !available() {
+        return Ok(None);
+    }
+
+    let abi = abi();
+    let file_access = AccessFs::from_file(abi) & !AccessFs::Execute;
+    let workdir_access = match output_file {
+        Some(_) => AccessFs::from_read(abi),
+        None => AccessFs::from_all(abi),
+    };
+    let output_file = output_file.map(|file| workdir.join(file));
+
+    let ruleset = (|| -> Result<_, RulesetError> {
+        Ruleset::default()
+            .handle_access(AccessFs::from_all(abi))?
+            .create()?
+            .add_rules(path_beneath_rules(toolchain, AccessFs::from_read(abi)))?
+            .add_rules(path_beneath_rules(DEVICES, file_access))?
+            .add_rules(path_beneath_rules([workdir], workdir_access))?
+            .add_rules(path_beneath_rules(output_file, file_access))
+    })();
+
+    ruleset.map(Option::from).map_err(io::Error::other)
+}
+
+/// Adds rules to `ruleset` which allow using the private `/tmp` of the minimal root, except for
+/// executing what's written there, and reading its `/proc`.
+///
+/// # Safety
+///
+/// Must only be called in a process forked from the judge, after entering the minimal root.
+pub unsafe fn allow_private_mounts(ruleset: RawFd) -> io::Result<()> {
+    let abi = abi();
+    add_path_beneath_rule(
+        ruleset,
+        c"/tmp",
+        AccessFs::from_all(abi) & !AccessFs::Execute,
+    )?;
+    add_path_beneath_rule(
+        ruleset,
+        c"/proc",
+        AccessFs::from_read(abi) & !AccessFs::Execute,
+    )
+}
+
+unsafe fn add_path_beneath_rule(
+    ruleset: RawFd,
+    path: &CStr,
+    access: BitFlags<AccessFs>,
+) -> io::Result<()> {
+    let fd = cvt(libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC))?;
+    let attr = PathBeneathAttr {
+        allowed_access: access.bits(),
+        parent_fd: fd,
+    };
+    let result = libc::syscall(
+        libc::SYS_landlock_add_rule,
+        ruleset,
+        LANDLOCK_RULE_PATH_BENEATH,
+        &attr as *const PathBeneathAttr,
+        0,
+    );
+    libc::close(fd);
+
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Restricts the calling process with `ruleset`, which is left open so that every run forked
+/// from a zygote can restrict itself with it.
+///
+/// # Safety
+///
+/// Must only be called in a process forked from the judge, after the filesystem has been set up
+/// since Landlock forbids any further mounts.
+pub unsafe fn restrict_self(ruleset: RawFd) -> io::Result<()> {
+    cvt(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+    if libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
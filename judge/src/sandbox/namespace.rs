@@ -0,0 +1,223 @@
+use std::{
+    ffi::CStr,
+    io,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+
+use libc::{c_int, pid_t};
+
+use super::{cvt, trace, trace::Tracer};
+
+/// User and group ID that the sandboxed process runs as inside its user namespace
+const SANDBOX_ID: u32 = 1000;
+const HOSTNAME: &[u8] = b"sandbox";
+/// Signal asking the supervisor to kill the sandboxed process tree
+const TERMINATE: c_int = libc::SIGALRM;
+
+/// Set once the supervisor receives TERMINATE
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+/// The process waited for by the supervisor, which TERMINATE kills right away once it's known
+static SANDBOXED: AtomicI32 = AtomicI32::new(0);
+
+const CLONE_FLAGS: c_int = libc::CLONE_NEWUSER
+    | libc::CLONE_NEWNS
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS;
+
+/// Namespace configuration for a sandboxed process.
+///
+/// Everything that requires allocation is prepared in the parent, since [`Namespaces::enter`]
+/// runs in the forked child where only async-signal-safe operations are permitted.
+#[derive(Debug, Clone)]
+pub struct Namespaces {
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+}
+
+impl Namespaces {
+    /// Maps the sandbox user to `id` as both UID and GID, or to the judge's own user.
+    pub fn new(id: Option<u32>) -> Self {
+        let (uid, gid) = match id {
+            Some(id) => (id, id),
+            None => unsafe { (libc::geteuid(), libc::getegid()) },
+        };
+
+        Namespaces {
+            uid_map: format!("{SANDBOX_ID} {uid} 1").into_bytes(),
+            gid_map: format!("{SANDBOX_ID} {gid} 1").into_bytes(),
+        }
+    }
+
+    /// Moves the calling process into fresh user, mount, PID, network, IPC and UTS namespaces.
+    ///
+    /// Since `unshare(CLONE_NEWPID)` only affects future children, this forks once more: the
+    /// calling process stays behind as a supervisor which mirrors the exit status of its child,
+    /// while the child returns from this function as PID 1 of the new PID namespace. If a
+    /// `tracer` is given, the supervisor also traces the child and its descendants.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called from a `pre_exec` hook or in a process forked from the judge.
+    pub unsafe fn enter(&self, tracer: Option<&Tracer>) -> io::Result<()> {
+        cvt(libc::unshare(CLONE_FLAGS))?;
+
+        write_file(c"/proc/self/setgroups", b"deny")?;
+        write_file(c"/proc/self/uid_map", &self.uid_map)?;
+        write_file(c"/proc/self/gid_map", &self.gid_map)?;
+
+        // Stop mount events from propagating back to the host
+        cvt(libc::mount(
+            std::ptr::null(),
+            c"/".as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        ))?;
+        cvt(libc::sethostname(HOSTNAME.as_ptr().cast(), HOSTNAME.len()))?;
+
+        match spawn(tracer)? {
+            0 => Ok(()),
+            pid => supervise(pid, tracer),
+        }
+    }
+}
+
+/// Moves future children into a fresh PID namespace and forks once more, like
+/// [`Namespaces::enter`] but for a process which is already inside the other namespaces.
+///
+/// Returns the PID of the child in the calling process, which should [`wait`] for it, and 0 in
+/// the child.
+///
+/// # Safety
+///
+/// Must only be called in a process forked from the judge.
+pub unsafe fn enter_pid(tracer: Option<&Tracer>) -> io::Result<pid_t> {
+    cvt(libc::unshare(libc::CLONE_NEWPID))?;
+    spawn(tracer)
+}
+
+/// Forks the process which becomes PID 1 of the PID namespace that was just unshared.
+unsafe fn spawn(tracer: Option<&Tracer>) -> io::Result<pid_t> {
+    // Installed before forking so that the signal can't kill the supervisor outright. The
+    // child's copy is reset by exec.
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = interrupt as extern "C" fn(c_int) as libc::sighandler_t;
+    cvt(libc::sigaction(TERMINATE, &action, std::ptr::null_mut()))?;
+
+    match cvt(libc::fork())? {
+        0 => {
+            // Take down the whole namespace if the supervisor is killed
+            cvt(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+
+            if tracer.is_some() {
+                trace::trace_me()?;
+            }
+
+            Ok(0)
+        }
+        pid => Ok(pid),
+    }
+}
+
+/// Kills the process tree of the sandbox supervised by `supervisor`.
+///
+/// The supervisor stays alive to reap the sandboxed process, so its resource usage is still
+/// reported by `wait4`.
+pub fn terminate(supervisor: pid_t) -> io::Result<()> {
+    cvt(unsafe { libc::kill(supervisor, TERMINATE) })?;
+    Ok(())
+}
+
+extern "C" fn interrupt(_: c_int) {
+    TERMINATED.store(true, Ordering::SeqCst);
+
+    // Killed here rather than after waitpid() returns, which it may never do if the signal
+    // arrives while the supervisor is busy resuming a tracee
+    let pid = SANDBOXED.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+}
+
+/// Waits for the sandboxed process and exits with the same status.
+unsafe fn supervise(pid: pid_t, tracer: Option<&Tracer>) -> ! {
+    // Release our copies of the stdio pipes and the exec error pipe held by the standard library,
+    // otherwise the parent would only observe EOF once the supervisor itself exits
+    libc::close_range(0, u32::MAX, 0);
+
+    let status = wait(pid, tracer);
+
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+/// Waits for the sandboxed process `pid`, tracing it and its descendants if a `tracer` is given,
+/// and returns its wait status. The sandbox is killed once [`terminate`] is called.
+///
+/// # Safety
+///
+/// Must only be called in a process forked from the judge, which has no other children.
+pub unsafe fn wait(pid: pid_t, tracer: Option<&Tracer>) -> c_int {
+    // Tracees other than the sandboxed process itself are only reported with __WALL
+    let target = if tracer.is_some() { -1 } else { pid };
+    let proc = if tracer.is_some() {
+        trace::open_proc()
+    } else {
+        -1
+    };
+
+    SANDBOXED.store(pid, Ordering::SeqCst);
+
+    let mut status = 0;
+    loop {
+        // Also covers TERMINATE arriving before the sandboxed process was known. Killing PID 1
+        // takes down the rest of the namespace.
+        if TERMINATED.load(Ordering::SeqCst) {
+            libc::kill(pid, libc::SIGKILL);
+        }
+
+        let waited = libc::waitpid(target, &mut status, libc::__WALL);
+
+        if waited < 0 {
+            if *libc::__errno_location() != libc::EINTR {
+                libc::_exit(127);
+            }
+        } else if waited == pid && !libc::WIFSTOPPED(status) {
+            break;
+        } else if let Some(tracer) = tracer {
+            trace::resume(pid, waited, status, tracer, proc);
+        }
+    }
+
+    match tracer.and_then(Tracer::fatal_signal) {
+        // The status of a process killed by a signal is only the signal number
+        Some(signal) if libc::WIFSIGNALED(status) => signal,
+        _ => status,
+    }
+}
+
+unsafe fn write_file(path: &CStr, contents: &[u8]) -> io::Result<()> {
+    let fd = cvt(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+    let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+    libc::close(fd);
+
+    if written < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    env,
+    os::{fd::RawFd, unix::process::ExitStatusExt},
+    path::Path,
+    process::Stdio,
+};
+
+use tokio::{
+    io::{Error, ErrorKind},
+    process::{Child, Command},
+};
+
+use super::{
+    cgroup::{self, Cgroup},
+    cpu::{self, Core},
+    filesystem::Filesystem,
+    landlock, namespace,
+    namespace::Namespaces,
+    perf, resource, slot,
+    trace::Tracer,
+    wait, BoxFuture, Output, Process, Profile, ResourceLimits, Sandbox, Slot, Streams,
+};
+
+/// Runs each command in fresh namespaces with a minimal root, confined by a cgroup, Landlock,
+/// rlimits and, for submissions, a traced seccomp policy.
+///
+/// Each of these is skipped with a warning if the kernel doesn't support it.
+#[derive(Debug)]
+pub struct Namespaced;
+
+impl Namespaced {
+    /// Detects the available sandboxing features, logging any fallbacks.
+    pub fn new() -> Self {
+        cgroup::available();
+        cpu::available();
+        slot::available();
+        landlock::available();
+        perf::available();
+        Namespaced
+    }
+}
+
+impl Default for Namespaced {
+    fn default() -> Self {
+        Namespaced::new()
+    }
+}
+
+impl Sandbox for Namespaced {
+    fn run<'a>(
+        &'a self,
+        dir: &'a Path,
+        command: &'a [String],
+        streams: Streams<'a>,
+        profile: Profile,
+        slot: &'a Slot<'_>,
+    ) -> BoxFuture<'a, Result<Output, Error>> {
+        Box::pin(run(dir, command, streams, profile, slot))
+    }
+}
+
+async fn run(
+    dir: &Path,
+    command: &[String],
+    streams: Streams<'_>,
+    profile: Profile,
+    slot: &Slot<'_>,
+) -> Result<Output, Error> {
+    let resource_limits = profile.resource_limits();
+
+    let core = match profile {
+        Profile::Interact(_) => None,
+        Profile::Compile(_) | Profile::Check(_) | Profile::Run(..) => cpu::acquire().await,
+    };
+    let cpu = core.as_ref().map(Core::cpu);
+    let cgroup = Cgroup::create(resource_limits, cpu)?;
+    let cgroup_procs = cgroup.as_ref().map(Cgroup::procs_fd);
+
+    let reap = move |exit_status, resource_usage| {
+        let mut output = Output {
+            exit_status,
+            resource_usage,
+            ..Output::default()
+        };
+
+        if let Some(cgroup) = cgroup {
+            cgroup.update_usage(&mut output.resource_usage)?;
+            output.memory_limit_exceeded = cgroup.memory_limit_exceeded()?;
+            output.process_limit_exceeded = cgroup.process_limit_exceeded()?;
+        }
+
+        Ok(output)
+    };
+
+    let (seccomp, toolchain, output_file) = match &profile {
+        Profile::Compile(_) | Profile::Check(_) | Profile::Interact(_) => {
+            let mut filesystem = Filesystem::new(dir)?;
+            if let Profile::Compile(_) = profile {
+                // Where the contests, their tests and other submissions are
+                filesystem = filesystem.hide(&env::current_dir()?)?;
+            }
+
+            let mut child = spawn(
+                dir,
+                command,
+                resource_limits,
+                filesystem,
+                cgroup_procs,
+                cpu,
+                slot.id(),
+            )?;
+
+            return wait(
+                Process::from_child(&mut child),
+                streams,
+                resource_limits,
+                namespace::terminate,
+                move |pid| {
+                    let (exit_status, resource_usage) = resource::wait4(pid)?;
+                    reap(exit_status, resource_usage)
+                },
+            )
+            .await;
+        }
+        Profile::Run(_, seccomp, toolchain, output_file) => (seccomp, toolchain, output_file),
+    };
+
+    // Submissions are always traced, to measure their memory and catch seccomp violations
+    let tracer = Tracer::new()?;
+    let zygote = slot.zygote(dir, command, seccomp, toolchain, output_file.as_deref())?;
+    let (process, run) = zygote
+        .start(resource_limits, cgroup_procs, cpu, Some(&tracer))
+        .await?;
+
+    let mut output = wait(
+        process,
+        streams,
+        resource_limits,
+        namespace::terminate,
+        move |_| {
+            let (exit_status, resource_usage, instructions) = run.wait()?;
+            let output = reap(exit_status, resource_usage)?;
+            Ok(Output {
+                instructions,
+                ..output
+            })
+        },
+    )
+    .await?;
+
+    // Both wait4 and the cgroup also count what the supervisor inherited from the judge
+    if let Some(peak_memory) = tracer.peak_memory() {
+        output.resource_usage.memory = peak_memory;
+    }
+
+    // A stack overflow is only a segmentation fault, but the stack can't have been much smaller
+    // than the limit when it happened
+    let stack_size = resource_limits.stack_size();
+    output.stack_limit_exceeded = output.exit_status.signal() == Some(libc::SIGSEGV)
+        && tracer.peak_stack() >= stack_size - stack_size / 8;
+    output.file_size_limit_exceeded |= tracer.file_size_exceeded();
+    output.open_file_limit_exceeded = tracer.peak_open_files() >= resource_limits.open_files;
+    output.security_violation = tracer.violation();
+
+    Ok(output)
+}
+
+/// Spawns a trusted command, such as a compiler or an interactor, which sees `filesystem`.
+fn spawn(
+    dir: &Path,
+    command: &[String],
+    resource_limits: ResourceLimits,
+    filesystem: Filesystem,
+    cgroup_procs: Option<RawFd>,
+    cpu: Option<usize>,
+    id: Option<u32>,
+) -> Result<Child, Error> {
+    let (executable, args) = command
+        .split_first()
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "empty command"))?;
+
+    let mut cmd = Command::new(executable);
+    cmd.args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let namespaces = Namespaces::new(id);
+
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(fd) = cgroup_procs {
+                cgroup::join(fd)?;
+            }
+
+            if let Some(cpu) = cpu {
+                cpu::pin(cpu)?;
+            }
+
+            if let Some(id) = id {
+                slot::switch_user(id)?;
+            }
+
+            namespaces.enter(None)?;
+
+            filesystem.enter()?;
+
+            resource_limits.set()?;
+            resource_limits.set_memory(cgroup_procs.is_some())?;
+            if cgroup_procs.is_none() {
+                resource_limits.set_processes()?;
+            }
+
+            Ok(())
+        });
+    }
+
+    cmd.spawn()
+}
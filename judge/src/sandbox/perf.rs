@@ -0,0 +1,112 @@
+use std::{
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+
+use libc::c_int;
+use once_cell::sync::Lazy;
+
+/// `PERF_TYPE_HARDWARE`
+const TYPE_HARDWARE: u32 = 0;
+/// `PERF_COUNT_HW_INSTRUCTIONS`
+const COUNT_INSTRUCTIONS: u64 = 1;
+/// `PERF_FLAG_FD_CLOEXEC`
+const FLAG_FD_CLOEXEC: libc::c_ulong = 8;
+
+const DISABLED: u64 = 1 << 0;
+const INHERIT: u64 = 1 << 1;
+const PINNED: u64 = 1 << 2;
+const EXCLUDE_KERNEL: u64 = 1 << 5;
+const EXCLUDE_HV: u64 = 1 << 6;
+const ENABLE_ON_EXEC: u64 = 1 << 12;
+
+/// The first version of `perf_event_attr`, which the kernel still accepts and has every field
+/// needed here. The flags are bitfields in the kernel's definition.
+#[repr(C)]
+struct Attr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+static AVAILABLE: Lazy<bool> = Lazy::new(|| match counter() {
+    Ok(_) => {
+        tracing::info!("instruction counters available");
+        true
+    }
+    Err(e) => {
+        tracing::warn!("instruction counters unavailable, runs are timed by CPU time: {e}");
+        false
+    }
+});
+
+/// Returns whether retired instructions can be counted.
+pub fn available() -> bool {
+    *AVAILABLE
+}
+
+/// Opens a counter of the user-space instructions retired by the children of the calling
+/// process, which only counts once they execute something.
+///
+/// Children inherit the counter when they're forked, and their counts are added to it as they
+/// exit, so it can be read with [`read`] once the whole process tree has been reaped. The
+/// calling process itself never executes anything, so it isn't counted.
+///
+/// Doesn't allocate, so that it can be used in a process forked from the judge.
+pub fn counter() -> io::Result<OwnedFd> {
+    let attr = Attr {
+        kind: TYPE_HARDWARE,
+        size: mem::size_of::<Attr>() as u32,
+        config: COUNT_INSTRUCTIONS,
+        sample_period: 0,
+        sample_type: 0,
+        read_format: 0,
+        // Pinned so that it's never multiplexed with other counters, which would leave gaps
+        flags: DISABLED | INHERIT | PINNED | EXCLUDE_KERNEL | EXCLUDE_HV | ENABLE_ON_EXEC,
+        wakeup_events: 0,
+        bp_type: 0,
+        config1: 0,
+    };
+
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const Attr,
+            0 as libc::pid_t,
+            -1 as c_int,
+            -1 as c_int,
+            FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Reads the instructions counted by `counter`.
+pub fn read(counter: &impl AsRawFd) -> io::Result<u64> {
+    let mut count = 0u64;
+    let read = unsafe {
+        libc::read(
+            counter.as_raw_fd(),
+            (&mut count as *mut u64).cast(),
+            mem::size_of::<u64>(),
+        )
+    };
+
+    match read {
+        ..=-1 => Err(io::Error::last_os_error()),
+        8 => Ok(count),
+        // A pinned counter which lost its hardware counter reads as end of file
+        _ => Err(io::Error::other("instruction counter was descheduled")),
+    }
+}
@@ -1,4 +1,6 @@
-use std::{io, os::unix::process::ExitStatusExt, process::ExitStatus, time::Duration};
+use std::{
+    io, num::NonZeroU64, os::unix::process::ExitStatusExt, process::ExitStatus, time::Duration,
+};
 
 use rlimit::{setrlimit, Resource};
 use schemars::JsonSchema;
@@ -11,7 +13,9 @@ pub struct ResourceUsage {
     pub user_time: Duration,
     /// System time
     pub sys_time: Duration,
-    /// Memory usage (bytes)
+    /// Wall-clock time
+    pub wall_time: Duration,
+    /// Peak resident memory (bytes)
     pub memory: u64,
 }
 
@@ -25,12 +29,15 @@ impl ResourceUsage {
     }
 
     pub fn exceeded_time(&self, resource_limits: ResourceLimits) -> bool {
-        (self.total_time().as_secs_f64() - resource_limits.cpu as f64).abs()
-            <= resource_limits.cpu_tolerance
+        self.total_time().as_secs_f64()
+            >= resource_limits.cpu as f64 - resource_limits.cpu_tolerance
     }
 
     pub fn exceeded_memory(&self, resource_limits: ResourceLimits) -> bool {
-        self.memory.abs_diff(resource_limits.memory) <= resource_limits.memory_tolerance
+        self.memory
+            >= resource_limits
+                .memory
+                .saturating_sub(resource_limits.memory_tolerance)
     }
 }
 
@@ -39,18 +46,166 @@ impl ResourceUsage {
 pub struct ResourceLimits {
     /// CPU time (seconds)
     pub cpu: u64,
-    /// CPU time tolerance (seconds)
+    /// CPU time tolerance (seconds): usage this close to the limit also counts as exceeding it
+    #[serde(default)]
     pub cpu_tolerance: f64,
+    /// Wall-clock time (seconds), defaults to twice the CPU time plus one second
+    #[serde(default)]
+    pub wall: Option<u64>,
     /// Memory usage (bytes)
     pub memory: u64,
-    /// Memory usage tolerance (bytes)
+    /// Memory usage tolerance (bytes): usage this close to the limit also counts as exceeding it
+    #[serde(default)]
     pub memory_tolerance: u64,
+    /// Standard output size (bytes)
+    #[serde(default = "default_stdout")]
+    pub stdout: u64,
+    /// Standard error size (bytes)
+    #[serde(default = "default_stderr")]
+    pub stderr: u64,
+    /// Processes and threads, defaults to a single-threaded process
+    #[serde(default = "default_processes")]
+    pub processes: u64,
+    /// How the memory limit is enforced
+    #[serde(default)]
+    pub memory_mode: MemoryMode,
+    /// Stack size of each process, defaults to 8 MiB
+    #[serde(default)]
+    pub stack: StackLimit,
+    /// Size of each file written (bytes)
+    #[serde(default = "default_file_size")]
+    pub file_size: u64,
+    /// Open file descriptors of each process, including standard input and output
+    #[serde(default = "default_open_files")]
+    pub open_files: u64,
+    /// Core dump size (bytes), disabled by default
+    #[serde(default)]
+    pub core: u64,
+    /// How the CPU time of submissions is measured
+    #[serde(default)]
+    pub time_measurement: TimeMeasurement,
+}
+
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum MemoryMode {
+    /// Limit the resident memory of the whole run with the cgroup memory controller, falling back
+    /// to `data` when cgroups are unavailable
+    #[default]
+    Cgroup,
+    /// Limit the virtual address space of each process (RLIMIT_AS), which also counts memory that
+    /// is mapped but never touched
+    AddressSpace,
+    /// Limit the heap and data segment of each process (RLIMIT_DATA), which covers `brk` and
+    /// private writable mappings but not the stack
+    Data,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum StackLimit {
+    /// Stack size (bytes)
+    Bytes(u64),
+    /// Only bounded by the memory limit
+    Unlimited,
+}
+
+impl Default for StackLimit {
+    fn default() -> Self {
+        StackLimit::Bytes(8 << 20)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeMeasurement {
+    /// User and system time as reported by the kernel, which varies with the load of the host
+    /// and its CPU frequency
+    #[default]
+    Cpu,
+    /// Retired user-space instructions, converted to user time at a fixed rate. Falls back to
+    /// `cpu` when hardware counters are unavailable, such as in most virtual machines or outside
+    /// the namespaced sandbox. Counted runs are only killed by the wall-clock limit, and the CPU
+    /// time limit is checked once they exit.
+    #[serde(rename_all = "kebab-case")]
+    Instructions {
+        /// Instructions per second of user time
+        per_second: NonZeroU64,
+    },
+}
+
+impl TimeMeasurement {
+    /// The user time that `instructions` retired instructions amount to, if they're counted.
+    pub fn user_time(&self, instructions: u64) -> Option<Duration> {
+        match self {
+            TimeMeasurement::Cpu => None,
+            TimeMeasurement::Instructions { per_second } => Some(Duration::from_secs_f64(
+                instructions as f64 / per_second.get() as f64,
+            )),
+        }
+    }
+}
+
+fn default_stdout() -> u64 {
+    64 << 20
+}
+
+fn default_stderr() -> u64 {
+    64 << 10
+}
+
+fn default_processes() -> u64 {
+    1
+}
+
+fn default_file_size() -> u64 {
+    64 << 20
+}
+
+fn default_open_files() -> u64 {
+    64
 }
 
 impl ResourceLimits {
+    pub fn wall_time(&self) -> Duration {
+        Duration::from_secs(self.wall.unwrap_or(self.cpu * 2 + 1))
+    }
+
+    pub fn stack_size(&self) -> u64 {
+        match self.stack {
+            StackLimit::Bytes(bytes) => bytes,
+            StackLimit::Unlimited => self.memory,
+        }
+    }
+
     pub fn set(&self) -> io::Result<()> {
-        setrlimit(Resource::CPU, self.cpu, self.cpu)?;
-        setrlimit(Resource::DATA, self.memory, self.memory)?;
+        // SIGXCPU is sent at the soft limit, so that runs killed by it can be told apart even if
+        // the time they're charged falls just short of it
+        setrlimit(Resource::CPU, self.cpu, self.cpu + 1)?;
+        setrlimit(Resource::STACK, self.stack_size(), self.stack_size())?;
+        setrlimit(Resource::FSIZE, self.file_size, self.file_size)?;
+        setrlimit(Resource::NOFILE, self.open_files, self.open_files)?;
+        setrlimit(Resource::CORE, self.core, self.core)?;
+        Ok(())
+    }
+
+    /// Per-process memory limit, unless it's enforced by the cgroup the run is placed in
+    pub fn set_memory(&self, cgroup: bool) -> io::Result<()> {
+        let resource = match self.memory_mode {
+            MemoryMode::Cgroup if cgroup => return Ok(()),
+            MemoryMode::Cgroup | MemoryMode::Data => Resource::DATA,
+            MemoryMode::AddressSpace => Resource::AS,
+        };
+        setrlimit(resource, self.memory, self.memory)?;
+        Ok(())
+    }
+
+    /// Fallback process limit for when the run isn't placed in a cgroup. Processes are counted
+    /// per user inside the sandbox's user namespace, including the supervisor, but the limit isn't
+    /// enforced if the judge runs as root.
+    pub fn set_processes(&self) -> io::Result<()> {
+        let limit = self.processes + 1;
+        setrlimit(Resource::NPROC, limit, limit)?;
         Ok(())
     }
 }
@@ -65,20 +220,22 @@ pub fn wait4(pid: i32) -> io::Result<(ExitStatus, ResourceUsage)> {
         Err(io::Error::last_os_error())
     } else {
         let rusage = unsafe { rusage.assume_init() };
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as i64;
+        Ok((ExitStatus::from_raw(status), usage(&rusage)))
+    }
+}
 
-        let convert = |timeval: libc::timeval| {
-            let duration = timeval.tv_sec * 1_000_000 + timeval.tv_usec;
-            Duration::from_micros(duration as u64)
-        };
+/// Converts `rusage` as reported by the kernel, without the wall-clock time.
+pub fn usage(rusage: &libc::rusage) -> ResourceUsage {
+    let convert = |timeval: libc::timeval| {
+        let duration = timeval.tv_sec * 1_000_000 + timeval.tv_usec;
+        Duration::from_micros(duration as u64)
+    };
 
-        Ok((
-            ExitStatus::from_raw(status),
-            ResourceUsage {
-                user_time: convert(rusage.ru_utime),
-                sys_time: convert(rusage.ru_stime),
-                memory: (rusage.ru_maxrss * page_size) as u64,
-            },
-        ))
+    ResourceUsage {
+        user_time: convert(rusage.ru_utime),
+        sys_time: convert(rusage.ru_stime),
+        wall_time: Duration::ZERO,
+        // Kilobytes on Linux
+        memory: rusage.ru_maxrss as u64 * 1024,
     }
 }
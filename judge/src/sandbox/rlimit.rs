@@ -0,0 +1,146 @@
+use std::{os::fd::AsRawFd, path::Path, process::Stdio};
+
+use tokio::{
+    io::{Error, ErrorKind},
+    process::Command,
+};
+
+use super::{
+    cpu::{self, Core},
+    cvt, landlock, resource, slot, wait, BoxFuture, Output, Process, Profile, Sandbox, Slot,
+    Streams,
+};
+
+/// Runs each command directly on the host, only confined by rlimits and, for submissions, an
+/// untraced seccomp policy and a Landlock ruleset.
+///
+/// This is meant for hosts where namespaces are unavailable, such as some containers. Since
+/// nothing is traced, forbidden syscalls are only reported as the process being killed by
+/// `SIGSYS`, only the limits enforced by rlimits are detected, and submissions may always execute
+/// other programs.
+#[derive(Debug)]
+pub struct Rlimit;
+
+impl Rlimit {
+    /// Detects the available sandboxing features, logging any fallbacks.
+    pub fn new() -> Self {
+        tracing::warn!("sandboxes only run with rlimits, seccomp and Landlock, without namespaces");
+        cpu::available();
+        slot::available();
+        landlock::available();
+        Rlimit
+    }
+}
+
+impl Default for Rlimit {
+    fn default() -> Self {
+        Rlimit::new()
+    }
+}
+
+impl Sandbox for Rlimit {
+    fn run<'a>(
+        &'a self,
+        dir: &'a Path,
+        command: &'a [String],
+        streams: Streams<'a>,
+        profile: Profile,
+        slot: &'a Slot<'_>,
+    ) -> BoxFuture<'a, Result<Output, Error>> {
+        Box::pin(run(dir, command, streams, profile, slot))
+    }
+}
+
+async fn run(
+    dir: &Path,
+    command: &[String],
+    streams: Streams<'_>,
+    profile: Profile,
+    slot: &Slot<'_>,
+) -> Result<Output, Error> {
+    let resource_limits = profile.resource_limits();
+
+    let core = match profile {
+        Profile::Interact(_) => None,
+        Profile::Compile(_) | Profile::Check(_) | Profile::Run(..) => cpu::acquire().await,
+    };
+    let cpu = core.as_ref().map(Core::cpu);
+
+    let mut child = {
+        let (executable, args) = command
+            .split_first()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "empty command"))?;
+
+        let mut cmd = Command::new(executable);
+        cmd.args(args)
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let id = slot.id();
+        let (seccomp, ruleset) = match profile {
+            Profile::Compile(_) | Profile::Check(_) | Profile::Interact(_) => (None, None),
+            Profile::Run(_, seccomp, toolchain, output_file) => {
+                // Without a minimal root, this is all that keeps submissions away from the host
+                let ruleset = landlock::ruleset(dir, &toolchain, output_file.as_deref())?;
+                (Some((seccomp, output_file.is_some())), ruleset)
+            }
+        };
+
+        unsafe {
+            cmd.pre_exec(move || {
+                // Its own process group, so that it can be killed along with its children
+                cvt(libc::setpgid(0, 0))?;
+
+                if let Some(cpu) = cpu {
+                    cpu::pin(cpu)?;
+                }
+
+                if let Some(id) = id {
+                    slot::switch_user(id)?;
+                }
+
+                resource_limits.set()?;
+                resource_limits.set_memory(false)?;
+                resource_limits.set_processes()?;
+
+                if let Some(ruleset) = &ruleset {
+                    landlock::restrict_self(ruleset.as_raw_fd())?;
+                }
+
+                if let Some((seccomp, output_file)) = &seccomp {
+                    seccomp
+                        .apply(*output_file, false)
+                        .map_err(|e| Error::other(format!("seccomp failed: {e}")))?;
+                }
+
+                Ok(())
+            });
+        }
+
+        cmd.spawn()?
+    };
+
+    wait(
+        Process::from_child(&mut child),
+        streams,
+        resource_limits,
+        terminate,
+        |pid| {
+            let (exit_status, resource_usage) = resource::wait4(pid)?;
+            Ok(Output {
+                exit_status,
+                resource_usage,
+                ..Output::default()
+            })
+        },
+    )
+    .await
+}
+
+/// Kills the process group of the sandboxed process.
+fn terminate(pid: i32) -> Result<(), Error> {
+    cvt(unsafe { libc::kill(-pid, libc::SIGKILL) })?;
+    Ok(())
+}
@@ -1,161 +1,483 @@
-use std::env::consts::ARCH;
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    env::consts::ARCH,
+    fmt,
+    sync::Arc,
+};
 
 use libc::*;
 use once_cell::sync::Lazy;
+use schemars::JsonSchema;
 use seccompiler::{
-    BpfProgram, Result, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
-    SeccompFilter, SeccompRule,
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule,
 };
+use serde::Deserialize;
+use thiserror::Error;
+
+pub(super) mod syscalls;
+
+/// Name of the built-in policy, used by languages which don't specify one
+pub const DEFAULT_POLICY: &str = "default";
+
+/// Data of a traced `execve` under a policy which denies violations
+pub(super) const TRACE_EXEC_DENY: u32 = 0;
+/// Data of a traced `execve` under a policy which kills on violations
+pub(super) const TRACE_EXEC_KILL: u32 = 1;
+
+static DEFAULT_PROGRAM: Lazy<SeccompProgram> = Lazy::new(|| {
+    SeccompProgram::new(default_rules(), OnViolation::default())
+        .expect("failed to compile default seccomp policy")
+});
+
+type Rules = BTreeMap<i64, Vec<SeccompRule>>;
+
+fn default_rules() -> Rules {
+    [
+        (SYS_access, vec![]),
+        (SYS_arch_prctl, vec![]),
+        (SYS_brk, vec![]),
+        (SYS_clock_getres, vec![]),
+        (SYS_clock_gettime, vec![]),
+        (SYS_clone3, vec![]),
+        (SYS_clone, vec![]),
+        (SYS_close, vec![]),
+        (SYS_dup2, vec![]),
+        (SYS_dup3, vec![]),
+        (SYS_dup, vec![]),
+        (SYS_clock_gettime, vec![]),
+        (SYS_clock_nanosleep, vec![]),
+        (SYS_epoll_create1, vec![]),
+        (SYS_epoll_create, vec![]),
+        (SYS_epoll_ctl, vec![]),
+        (SYS_epoll_pwait, vec![]),
+        (SYS_epoll_wait, vec![]),
+        (SYS_execve, vec![]),
+        (SYS_exit_group, vec![]),
+        (SYS_exit, vec![]),
+        (SYS_fcntl, vec![]),
+        (SYS_fstat, vec![]),
+        (SYS_futex, vec![]),
+        (SYS_getcwd, vec![]),
+        (SYS_getdents64, vec![]),
+        (SYS_getdents, vec![]),
+        (SYS_getegid, vec![]),
+        (SYS_geteuid, vec![]),
+        (SYS_getgid, vec![]),
+        (SYS_getpgrp, vec![]),
+        (SYS_getpid, vec![]),
+        (SYS_getppid, vec![]),
+        (SYS_getrandom, vec![]),
+        (SYS_getrlimit, vec![]),
+        (SYS_getrusage, vec![]),
+        (SYS_gettid, vec![]),
+        (SYS_gettimeofday, vec![]),
+        (SYS_getuid, vec![]),
+        (SYS_ioctl, vec![]),
+        (SYS_lseek, vec![]),
+        (SYS_madvise, vec![]),
+        (SYS_mmap, vec![]),
+        (SYS_modify_ldt, vec![]),
+        (SYS_mprotect, vec![]),
+        (SYS_mremap, vec![]),
+        (SYS_munmap, vec![]),
+        (SYS_nanosleep, vec![]),
+        (SYS_newfstatat, vec![]),
+        (
+            SYS_open,
+            vec![
+                SeccompRule::new(vec![SeccompCondition::new(
+                    1,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    O_RDONLY as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+                SeccompRule::new(vec![SeccompCondition::new(
+                    1,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    (O_RDONLY | O_LARGEFILE | O_CLOEXEC) as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+            ],
+        ),
+        (
+            SYS_openat,
+            vec![
+                SeccompRule::new(vec![SeccompCondition::new(
+                    2,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    O_RDONLY as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+                SeccompRule::new(vec![SeccompCondition::new(
+                    2,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    (O_RDONLY | O_CLOEXEC) as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+                SeccompRule::new(vec![SeccompCondition::new(
+                    2,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::Eq,
+                    (O_RDONLY | O_NONBLOCK | O_CLOEXEC | O_DIRECTORY) as u64,
+                )
+                .unwrap()])
+                .unwrap(),
+            ],
+        ),
+        (SYS_pipe2, vec![]),
+        (SYS_pipe, vec![]),
+        (SYS_poll, vec![]),
+        (SYS_ppoll, vec![]),
+        (SYS_pread64, vec![]),
+        (SYS_readlinkat, vec![]),
+        (SYS_readlink, vec![]),
+        (SYS_read, vec![]),
+        (SYS_restart_syscall, vec![]),
+        (SYS_rseq, vec![]),
+        (SYS_rt_sigaction, vec![]),
+        (SYS_rt_sigprocmask, vec![]),
+        (SYS_rt_sigreturn, vec![]),
+        (SYS_sched_getaffinity, vec![]),
+        (SYS_sched_getparam, vec![]),
+        (SYS_sched_get_priority_max, vec![]),
+        (SYS_sched_get_priority_min, vec![]),
+        (SYS_sched_getscheduler, vec![]),
+        (SYS_sched_setscheduler, vec![]),
+        (SYS_sched_yield, vec![]),
+        (SYS_select, vec![]),
+        (SYS_set_robust_list, vec![]),
+        (SYS_set_thread_area, vec![]),
+        (SYS_set_tid_address, vec![]),
+        (SYS_sigaltstack, vec![]),
+        (SYS_statfs, vec![]),
+        (SYS_sysinfo, vec![]),
+        (SYS_timer_create, vec![]),
+        (SYS_timer_delete, vec![]),
+        (SYS_timerfd_create, vec![]),
+        (SYS_timer_settime, vec![]),
+        (SYS_time, vec![]),
+        (SYS_uname, vec![]),
+        (SYS_write, vec![]),
+        (SYS_writev, vec![]),
+    ]
+    .into()
+}
+
+/// Rules which also let a submission open files for writing, for tasks whose output is written to
+/// a file. Seccomp can't inspect paths, so Landlock limits this to the output file itself.
+fn output_file_rules() -> Rules {
+    let write = |index| {
+        [O_WRONLY, O_RDWR]
+            .map(|mode| {
+                SeccompRule::new(vec![SeccompCondition::new(
+                    index,
+                    SeccompCmpArgLen::Dword,
+                    SeccompCmpOp::MaskedEq(O_ACCMODE as u64),
+                    mode as u64,
+                )
+                .unwrap()])
+                .unwrap()
+            })
+            .to_vec()
+    };
+
+    [(SYS_open, write(1)), (SYS_openat, write(2))].into()
+}
+
+/// A named set of allowed syscalls, declared in the contest configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SeccompPolicy {
+    /// Policy whose syscalls are also allowed by this one, such as the built-in `default` policy
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub allow: Vec<SyscallRule>,
+    /// What happens when a syscall outside the policy is made, not inherited from `extends`
+    #[serde(default)]
+    pub on_violation: OnViolation,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnViolation {
+    /// The syscall fails with EPERM
+    #[default]
+    Deny,
+    /// The submission is killed and judged as a security violation. Policies which kill may have
+    /// to allow `prlimit64` with argument 2 equal to 0, which glibc uses to read resource limits.
+    Kill,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SyscallRule {
+    /// Allowed with any arguments
+    Any(String),
+    /// Allowed if all of the conditions hold. Multiple rules for the same syscall are alternatives.
+    Conditional {
+        syscall: String,
+        args: Vec<ArgCondition>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ArgCondition {
+    /// Argument index (0-5)
+    pub index: u8,
+    /// Argument size, defaults to qword
+    #[serde(default)]
+    pub len: ArgLen,
+    pub op: ArgOp,
+    pub value: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArgLen {
+    Dword,
+    #[default]
+    Qword,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArgOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Compares only the bits set in the mask
+    MaskedEq(u64),
+}
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("unknown seccomp policy `{0}`")]
+    UnknownPolicy(String),
+    #[error("seccomp policy `{0}` extends itself")]
+    Cycle(String),
+    #[error("the `{DEFAULT_POLICY}` seccomp policy is built in and can't be redefined")]
+    Redefined,
+    #[error("unknown syscall `{0}`")]
+    UnknownSyscall(String),
+    #[error("failed to compile seccomp filter: {0}")]
+    Compile(#[from] seccompiler::BackendError),
+}
+
+/// A compiled seccomp policy.
+#[derive(Clone)]
+pub struct SeccompProgram {
+    program: Arc<BpfProgram>,
+    /// The same policy along with [`output_file_rules`]
+    output_file_program: Arc<BpfProgram>,
+    /// Hands `execve` over to the tracer, unless the policy allows it
+    exec_program: Option<Arc<BpfProgram>>,
+    on_violation: OnViolation,
+}
+
+impl SeccompProgram {
+    fn new(mut rules: Rules, on_violation: OnViolation) -> Result<Self, PolicyError> {
+        // The filter is installed before the submission is executed, so this can't be denied.
+        // Instead, the tracer only lets the initial exec through.
+        let exec_program = match rules.insert(SYS_execve, vec![]) {
+            Some(rules) if rules.is_empty() => None,
+            _ => Some(Arc::new(exec_filter(on_violation)?)),
+        };
+
+        let mut output_file = rules.clone();
+        for (syscall, rules) in output_file_rules() {
+            for rule in rules {
+                add_rule(&mut output_file, syscall, rule);
+            }
+        }
+
+        Ok(SeccompProgram {
+            program: Arc::new(filter(rules, on_violation)?),
+            output_file_program: Arc::new(filter(output_file, on_violation)?),
+            exec_program,
+            on_violation,
+        })
+    }
+
+    pub fn on_violation(&self) -> OnViolation {
+        self.on_violation
+    }
 
-static SECCOMP_FILTER: Lazy<BpfProgram> = Lazy::new(|| {
+    /// Installs the filter, which also allows opening files for writing if `output_file` is set.
+    ///
+    /// Unless the policy allows `execve`, a `traced` process may only execute once, which the
+    /// tracer enforces. Otherwise it may always execute, since the filter can't tell the initial
+    /// exec apart.
+    pub fn apply(&self, output_file: bool, traced: bool) -> seccompiler::Result<()> {
+        // Installed first, since the policy may not allow installing more filters
+        if let (Some(exec_program), true) = (&self.exec_program, traced) {
+            seccompiler::apply_filter(exec_program)?;
+        }
+
+        if output_file {
+            seccompiler::apply_filter(&self.output_file_program)
+        } else {
+            seccompiler::apply_filter(&self.program)
+        }
+    }
+}
+
+fn filter(rules: Rules, on_violation: OnViolation) -> Result<BpfProgram, PolicyError> {
+    let mismatch_action = match on_violation {
+        OnViolation::Deny => SeccompAction::Errno(EPERM as u32),
+        // Raises SIGSYS, which the sandbox supervisor intercepts to record the syscall
+        OnViolation::Kill => SeccompAction::Trap,
+    };
     let filter = SeccompFilter::new(
-        [
-            (SYS_access, vec![]),
-            (SYS_arch_prctl, vec![]),
-            (SYS_brk, vec![]),
-            (SYS_clock_getres, vec![]),
-            (SYS_clock_gettime, vec![]),
-            (SYS_clone3, vec![]),
-            (SYS_clone, vec![]),
-            (SYS_close, vec![]),
-            (SYS_dup2, vec![]),
-            (SYS_dup3, vec![]),
-            (SYS_dup, vec![]),
-            (SYS_clock_gettime, vec![]),
-            (SYS_clock_nanosleep, vec![]),
-            (SYS_epoll_create1, vec![]),
-            (SYS_epoll_create, vec![]),
-            (SYS_epoll_ctl, vec![]),
-            (SYS_epoll_pwait, vec![]),
-            (SYS_epoll_wait, vec![]),
-            (SYS_execve, vec![]),
-            (SYS_exit_group, vec![]),
-            (SYS_exit, vec![]),
-            (SYS_fcntl, vec![]),
-            (SYS_fstat, vec![]),
-            (SYS_futex, vec![]),
-            (SYS_getcwd, vec![]),
-            (SYS_getdents64, vec![]),
-            (SYS_getdents, vec![]),
-            (SYS_getegid, vec![]),
-            (SYS_geteuid, vec![]),
-            (SYS_getgid, vec![]),
-            (SYS_getpgrp, vec![]),
-            (SYS_getpid, vec![]),
-            (SYS_getppid, vec![]),
-            (SYS_getrandom, vec![]),
-            (SYS_getrlimit, vec![]),
-            (SYS_getrusage, vec![]),
-            (SYS_gettid, vec![]),
-            (SYS_gettimeofday, vec![]),
-            (SYS_getuid, vec![]),
-            (SYS_ioctl, vec![]),
-            (SYS_lseek, vec![]),
-            (SYS_madvise, vec![]),
-            (SYS_mmap, vec![]),
-            (SYS_modify_ldt, vec![]),
-            (SYS_mprotect, vec![]),
-            (SYS_mremap, vec![]),
-            (SYS_munmap, vec![]),
-            (SYS_nanosleep, vec![]),
-            (SYS_newfstatat, vec![]),
-            (
-                SYS_open,
-                vec![
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        1,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        O_RDONLY as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        1,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_LARGEFILE | O_CLOEXEC) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                ],
-            ),
-            (
-                SYS_openat,
-                vec![
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        O_RDONLY as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_CLOEXEC) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                    SeccompRule::new(vec![SeccompCondition::new(
-                        2,
-                        SeccompCmpArgLen::Dword,
-                        SeccompCmpOp::Eq,
-                        (O_RDONLY | O_NONBLOCK | O_CLOEXEC | O_DIRECTORY) as u64,
-                    )
-                    .unwrap()])
-                    .unwrap(),
-                ],
-            ),
-            (SYS_pipe2, vec![]),
-            (SYS_pipe, vec![]),
-            (SYS_poll, vec![]),
-            (SYS_ppoll, vec![]),
-            (SYS_pread64, vec![]),
-            (SYS_readlinkat, vec![]),
-            (SYS_readlink, vec![]),
-            (SYS_read, vec![]),
-            (SYS_restart_syscall, vec![]),
-            (SYS_rseq, vec![]),
-            (SYS_rt_sigaction, vec![]),
-            (SYS_rt_sigprocmask, vec![]),
-            (SYS_rt_sigreturn, vec![]),
-            (SYS_sched_getaffinity, vec![]),
-            (SYS_sched_getparam, vec![]),
-            (SYS_sched_get_priority_max, vec![]),
-            (SYS_sched_get_priority_min, vec![]),
-            (SYS_sched_getscheduler, vec![]),
-            (SYS_sched_setscheduler, vec![]),
-            (SYS_sched_yield, vec![]),
-            (SYS_select, vec![]),
-            (SYS_set_robust_list, vec![]),
-            (SYS_set_thread_area, vec![]),
-            (SYS_set_tid_address, vec![]),
-            (SYS_sigaltstack, vec![]),
-            (SYS_statfs, vec![]),
-            (SYS_sysinfo, vec![]),
-            (SYS_timer_create, vec![]),
-            (SYS_timer_delete, vec![]),
-            (SYS_timerfd_create, vec![]),
-            (SYS_timer_settime, vec![]),
-            (SYS_time, vec![]),
-            (SYS_uname, vec![]),
-            (SYS_write, vec![]),
-            (SYS_writev, vec![]),
-        ]
-        .into(),
-        SeccompAction::Errno(EPERM as u32),
+        rules,
+        mismatch_action,
         SeccompAction::Allow,
         ARCH.try_into().expect("unsupported architecture"),
-    )
-    .expect("failed to create seccomp filter");
+    )?;
 
-    filter.try_into().expect("failed to compile seccomp filter")
-});
+    Ok(filter.try_into()?)
+}
+
+/// A filter which stops `execve` for the tracer, telling it how the policy handles violations.
+fn exec_filter(on_violation: OnViolation) -> Result<BpfProgram, PolicyError> {
+    let data = match on_violation {
+        OnViolation::Deny => TRACE_EXEC_DENY,
+        OnViolation::Kill => TRACE_EXEC_KILL,
+    };
+    let filter = SeccompFilter::new(
+        [(SYS_execve, vec![])].into(),
+        SeccompAction::Allow,
+        SeccompAction::Trace(data),
+        ARCH.try_into().expect("unsupported architecture"),
+    )?;
+
+    Ok(filter.try_into()?)
+}
+
+impl Default for SeccompProgram {
+    fn default() -> Self {
+        DEFAULT_PROGRAM.clone()
+    }
+}
+
+impl fmt::Debug for SeccompProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeccompProgram")
+            .field("instructions", &self.program.len())
+            .field("on_violation", &self.on_violation)
+            .finish()
+    }
+}
+
+impl PartialEq for SeccompProgram {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.program, &other.program)
+    }
+}
+
+/// Compiles each policy, along with the built-in default policy.
+pub fn compile(
+    policies: &BTreeMap<String, SeccompPolicy>,
+) -> Result<HashMap<String, SeccompProgram>, PolicyError> {
+    if policies.contains_key(DEFAULT_POLICY) {
+        return Err(PolicyError::Redefined);
+    }
+
+    let mut programs = HashMap::from([(DEFAULT_POLICY.to_owned(), SeccompProgram::default())]);
+    for name in policies.keys() {
+        let rules = resolve(policies, name, &mut Vec::new())?;
+        let program = SeccompProgram::new(rules, policies[name].on_violation)?;
+        programs.insert(name.clone(), program);
+    }
+
+    Ok(programs)
+}
+
+fn resolve<'a>(
+    policies: &'a BTreeMap<String, SeccompPolicy>,
+    name: &'a str,
+    visiting: &mut Vec<&'a str>,
+) -> Result<Rules, PolicyError> {
+    if name == DEFAULT_POLICY {
+        return Ok(default_rules());
+    }
+
+    let policy = policies
+        .get(name)
+        .ok_or_else(|| PolicyError::UnknownPolicy(name.to_owned()))?;
+
+    if visiting.contains(&name) {
+        return Err(PolicyError::Cycle(name.to_owned()));
+    }
+
+    visiting.push(name);
+    let mut rules = match &policy.extends {
+        Some(parent) => resolve(policies, parent, visiting)?,
+        None => Rules::new(),
+    };
+    visiting.pop();
+
+    for rule in &policy.allow {
+        match rule {
+            SyscallRule::Any(syscall) => {
+                // An empty list of rules matches unconditionally
+                rules.insert(lookup(syscall)?, vec![]);
+            }
+            SyscallRule::Conditional { syscall, args } => {
+                let conditions = args
+                    .iter()
+                    .map(|arg| {
+                        let len = match arg.len {
+                            ArgLen::Dword => SeccompCmpArgLen::Dword,
+                            ArgLen::Qword => SeccompCmpArgLen::Qword,
+                        };
+                        let op = match arg.op {
+                            ArgOp::Eq => SeccompCmpOp::Eq,
+                            ArgOp::Ne => SeccompCmpOp::Ne,
+                            ArgOp::Lt => SeccompCmpOp::Lt,
+                            ArgOp::Le => SeccompCmpOp::Le,
+                            ArgOp::Gt => SeccompCmpOp::Gt,
+                            ArgOp::Ge => SeccompCmpOp::Ge,
+                            ArgOp::MaskedEq(mask) => SeccompCmpOp::MaskedEq(mask),
+                        };
+                        SeccompCondition::new(arg.index, len, op, arg.value)
+                    })
+                    .collect::<Result<_, _>>()?;
+                let rule = SeccompRule::new(conditions)?;
+
+                add_rule(&mut rules, lookup(syscall)?, rule);
+            }
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Allows `syscall` when `rule` holds, unless it's already allowed unconditionally.
+fn add_rule(rules: &mut Rules, syscall: i64, rule: SeccompRule) {
+    match rules.entry(syscall) {
+        Entry::Vacant(entry) => {
+            entry.insert(vec![rule]);
+        }
+        Entry::Occupied(entry) if !entry.get().is_empty() => {
+            entry.into_mut().push(rule);
+        }
+        Entry::Occupied(_) => {}
+    }
+}
 
-pub fn apply_filters() -> Result<()> {
-    seccompiler::apply_filter(&SECCOMP_FILTER)?;
-    Ok(())
+fn lookup(syscall: &str) -> Result<i64, PolicyError> {
+    syscalls::number(syscall).ok_or_else(|| PolicyError::UnknownSyscall(syscall.to_owned()))
 }
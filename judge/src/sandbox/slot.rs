@@ -0,0 +1,238 @@
+use std::{
+    env, fs, io,
+    os::unix::fs::{chown, lchown, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use once_cell::sync::Lazy;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::{cvt, zygote::Zygote, SeccompProgram};
+
+/// First UID and GID of the pool, unless overridden by `SANDBOX_ID_BASE`
+const DEFAULT_BASE_ID: u32 = 60000;
+
+static BASE_ID: Lazy<Option<u32>> = Lazy::new(|| {
+    if unsafe { libc::geteuid() } != 0 {
+        tracing::warn!("not running as root, sandboxes run as the judge's own user");
+        return None;
+    }
+
+    let base = match env::var("SANDBOX_ID_BASE") {
+        Ok(base) => match base.parse() {
+            Ok(base) => base,
+            Err(e) => {
+                tracing::error!(
+                    "invalid SANDBOX_ID_BASE, sandboxes run as the judge's own user: {e}"
+                );
+                return None;
+            }
+        },
+        Err(_) => DEFAULT_BASE_ID,
+    };
+
+    tracing::info!("running sandboxes as UIDs and GIDs from {base}");
+    Some(base)
+});
+
+/// Returns whether each slot runs its sandboxes as a dedicated user.
+pub fn available() -> bool {
+    BASE_ID.is_some()
+}
+
+/// A fixed number of slots for sandboxes to run in concurrently.
+#[derive(Debug)]
+pub struct SlotPool {
+    base_id: Option<u32>,
+    free: Mutex<Vec<u32>>,
+    semaphore: Semaphore,
+}
+
+impl SlotPool {
+    /// A pool whose slots each run their sandboxes as a dedicated user, if the judge runs as root.
+    pub fn new(slots: usize) -> Self {
+        SlotPool::with_base_id(slots, *BASE_ID)
+    }
+
+    /// A pool whose slots all run their sandboxes as the judge's own user, and never change the
+    /// owner of any file or kill any process, such as for testing without privileges.
+    pub fn unprivileged(slots: usize) -> Self {
+        SlotPool::with_base_id(slots, None)
+    }
+
+    fn with_base_id(slots: usize, base_id: Option<u32>) -> Self {
+        SlotPool {
+            base_id,
+            free: Mutex::new((0..slots as u32).rev().collect()),
+            semaphore: Semaphore::new(slots),
+        }
+    }
+
+    /// Waits until a slot is free and claims it.
+    pub async fn acquire(&self) -> Slot<'_> {
+        let permit = self.semaphore.acquire().await.expect("semaphore closed");
+        let index = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("no free slot despite permit");
+
+        Slot {
+            pool: self,
+            index,
+            zygote: Mutex::new(None),
+            _permit: permit,
+        }
+    }
+}
+
+/// A claimed slot, whose user's processes are all killed when it's released.
+///
+/// Sandboxes in different slots run as different users, so they can't signal, trace or read the
+/// files of each other.
+#[derive(Debug)]
+pub struct Slot<'a> {
+    pool: &'a SlotPool,
+    index: u32,
+    zygote: Mutex<Option<Arc<Zygote>>>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Slot<'_> {
+    /// UID and GID of this slot, or `None` if sandboxes run as the judge's own user
+    pub fn id(&self) -> Option<u32> {
+        self.pool.base_id.map(|base| base + self.index)
+    }
+
+    /// Hands `dir` and the files in it over to this slot's user, and hides them from the others.
+    pub fn chown(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let Some(id) = self.id() else {
+            return Ok(());
+        };
+        let dir = dir.as_ref();
+
+        for entry in fs::read_dir(dir)? {
+            chown(entry?.path(), Some(id), Some(id))?;
+        }
+        chown(dir, Some(id), Some(id))?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+    }
+
+    /// Takes `dir` and everything in it back from this slot's user, leaving it readable by every
+    /// slot, and executable where it already was, but no longer writable.
+    pub fn release(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        if self.id().is_none() {
+            return Ok(());
+        }
+
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        release(dir.as_ref(), uid, gid)
+    }
+
+    /// The zygote of this slot for running `command` in `dir`, which is spawned unless the
+    /// current one already serves it.
+    pub(super) fn zygote(
+        &self,
+        dir: &Path,
+        command: &[String],
+        seccomp: &SeccompProgram,
+        toolchain: &[PathBuf],
+        output_file: Option<&Path>,
+    ) -> io::Result<Arc<Zygote>> {
+        let mut zygote = self.zygote.lock().unwrap();
+
+        match &*zygote {
+            Some(zygote) if zygote.serves(dir, command, seccomp, toolchain, output_file) => {
+                Ok(zygote.clone())
+            }
+            _ => {
+                let spawned = Arc::new(Zygote::spawn(
+                    dir,
+                    command,
+                    seccomp,
+                    toolchain,
+                    output_file,
+                    self.id(),
+                )?);
+                *zygote = Some(spawned.clone());
+                Ok(spawned)
+            }
+        }
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        // Closing its socket makes the zygote exit, even if it isn't killed below
+        self.zygote.get_mut().unwrap().take();
+
+        if let Some(id) = self.id() {
+            if let Err(e) = kill_all(id) {
+                tracing::error!("failed to kill processes of UID {id}: {e}");
+            }
+        }
+
+        self.pool.free.lock().unwrap().push(self.index);
+    }
+}
+
+/// Switches the calling process to `id`, dropping every privilege of the judge.
+///
+/// # Safety
+///
+/// Must only be called from a `pre_exec` hook or in a process forked from the judge.
+pub unsafe fn switch_user(id: u32) -> io::Result<()> {
+    cvt(libc::setgroups(0, std::ptr::null()))?;
+    cvt(libc::setresgid(id, id, id))?;
+    cvt(libc::setresuid(id, id, id))?;
+    // Changing UID makes the process undumpable, which would leave its /proc files owned by root
+    // and stop it from writing its own UID map
+    cvt(libc::prctl(libc::PR_SET_DUMPABLE, 1))?;
+    Ok(())
+}
+
+/// Kills every process running as `id`.
+fn kill_all(id: u32) -> io::Result<()> {
+    // Signalling every process from a child running as the same user reaches exactly those
+    // processes, including any forked while the signal is being sent
+    unsafe {
+        match cvt(libc::fork())? {
+            0 => {
+                if libc::setresuid(id, id, id) == 0 {
+                    libc::kill(-1, libc::SIGKILL);
+                }
+                libc::_exit(0);
+            }
+            pid => {
+                let mut status = 0;
+                cvt(libc::waitpid(pid, &mut status, 0))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives `path` and, if it's a directory, everything in it to `uid` and `gid`.
+fn release(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    lchown(path, Some(uid), Some(gid))?;
+    if metadata.is_symlink() {
+        return Ok(());
+    }
+
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            release(&entry?.path(), uid, gid)?;
+        }
+    }
+
+    let mode = if metadata.is_dir() || metadata.permissions().mode() & 0o100 != 0 {
+        0o755
+    } else {
+        0o644
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
@@ -0,0 +1,484 @@
+use std::{
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU64, Ordering},
+};
+
+use libc::{c_int, c_void, pid_t};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::{
+    cvt,
+    seccomp::{syscalls, TRACE_EXEC_KILL},
+};
+
+/// `si_code` of a SIGSYS raised by a seccomp filter
+const SYS_SECCOMP: c_int = 1;
+const NO_VIOLATION: i64 = -1;
+
+const OPTIONS: c_int = libc::PTRACE_O_EXITKILL
+    | libc::PTRACE_O_TRACECLONE
+    | libc::PTRACE_O_TRACEFORK
+    | libc::PTRACE_O_TRACEVFORK
+    | libc::PTRACE_O_TRACEEXEC
+    | libc::PTRACE_O_TRACEEXIT
+    | libc::PTRACE_O_TRACESECCOMP;
+
+/// A syscall made in violation of a seccomp policy
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Syscall {
+    pub number: i64,
+    pub name: Option<&'static str>,
+}
+
+/// What the sandbox supervisor observed while tracing a run.
+///
+/// This is backed by a shared mapping of a memfd, so that it can be written by the supervisor
+/// forked inside the sandbox, or by one forked from a zygote which was handed the memfd, and read
+/// back by the judge once the run is over.
+#[derive(Debug)]
+pub struct Tracer {
+    shared: NonNull<Shared>,
+    memfd: OwnedFd,
+}
+
+#[repr(C)]
+struct Shared {
+    /// Number of the first syscall which violated the seccomp policy
+    violation: AtomicI64,
+    /// Largest peak resident set size of any traced process, in bytes
+    peak_memory: AtomicU64,
+    /// Largest stack of any traced process, in bytes
+    peak_stack: AtomicU64,
+    /// Most file descriptors left open by any traced process
+    peak_open_files: AtomicU64,
+    /// Whether a traced process was sent SIGXFSZ
+    file_size_exceeded: AtomicBool,
+    /// Signal which the sandboxed process was killed in place of, or 0
+    fatal_signal: AtomicI32,
+    /// Whether the sandboxed process has executed its command
+    executed: AtomicBool,
+}
+
+// The mapping is only ever accessed atomically
+unsafe impl Send for Tracer {}
+unsafe impl Sync for Tracer {}
+
+impl Tracer {
+    pub fn new() -> io::Result<Self> {
+        let memfd = unsafe {
+            let fd = cvt(libc::memfd_create(c"tracer".as_ptr(), libc::MFD_CLOEXEC))?;
+            OwnedFd::from_raw_fd(fd)
+        };
+        cvt(unsafe { libc::ftruncate(memfd.as_raw_fd(), mem::size_of::<Shared>() as i64) })?;
+
+        let tracer = unsafe { Tracer::map(memfd)? };
+        unsafe {
+            tracer.shared.as_ptr().write(Shared {
+                violation: AtomicI64::new(NO_VIOLATION),
+                peak_memory: AtomicU64::new(0),
+                peak_stack: AtomicU64::new(0),
+                peak_open_files: AtomicU64::new(0),
+                file_size_exceeded: AtomicBool::new(false),
+                fatal_signal: AtomicI32::new(0),
+                executed: AtomicBool::new(false),
+            })
+        };
+        Ok(tracer)
+    }
+
+    /// Maps the tracer backed by `memfd`.
+    ///
+    /// # Safety
+    ///
+    /// `memfd` must be the [`Tracer::memfd`] of a tracer created with [`Tracer::new`].
+    pub unsafe fn map(memfd: OwnedFd) -> io::Result<Self> {
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            mem::size_of::<Shared>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            memfd.as_raw_fd(),
+            0,
+        );
+
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Tracer {
+            shared: NonNull::new(ptr.cast()).expect("mmap returned null"),
+            memfd,
+        })
+    }
+
+    pub fn memfd(&self) -> RawFd {
+        self.memfd.as_raw_fd()
+    }
+
+    pub fn violation(&self) -> Option<Syscall> {
+        match self.shared().violation.load(Ordering::SeqCst) {
+            NO_VIOLATION => None,
+            number => Some(Syscall {
+                number,
+                name: syscalls::name(number),
+            }),
+        }
+    }
+
+    /// Peak resident memory of the largest process (bytes), unless every process was killed
+    /// before it could be measured.
+    ///
+    /// Unlike `ru_maxrss`, this doesn't include memory the process inherited from the judge
+    /// before it was executed.
+    pub fn peak_memory(&self) -> Option<u64> {
+        match self.shared().peak_memory.load(Ordering::SeqCst) {
+            0 => None,
+            peak_memory => Some(peak_memory),
+        }
+    }
+
+    /// Largest stack of any process when it exited (bytes)
+    pub fn peak_stack(&self) -> u64 {
+        self.shared().peak_stack.load(Ordering::SeqCst)
+    }
+
+    /// Most file descriptors any process still had open when it exited
+    pub fn peak_open_files(&self) -> u64 {
+        self.shared().peak_open_files.load(Ordering::SeqCst)
+    }
+
+    /// Whether a process wrote past the file size limit, even if it handled the signal
+    pub fn file_size_exceeded(&self) -> bool {
+        self.shared().file_size_exceeded.load(Ordering::SeqCst)
+    }
+
+    /// Signal which the sandboxed process would have been killed by, had it not been PID 1.
+    pub fn fatal_signal(&self) -> Option<c_int> {
+        match self.shared().fatal_signal.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+
+    fn shared(&self) -> &Shared {
+        unsafe { self.shared.as_ref() }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.shared.as_ptr().cast(), mem::size_of::<Shared>()) };
+    }
+}
+
+/// The `_sigsys` member of the `siginfo_t` union, which the libc crate doesn't expose
+#[repr(C)]
+struct SigsysInfo {
+    _signo: c_int,
+    _errno: c_int,
+    code: c_int,
+    _call_addr: *mut c_void,
+    syscall: c_int,
+}
+
+/// Makes the calling process a tracee of its parent, the sandbox supervisor.
+///
+/// The process stops itself so that [`resume`] can set the tracing options before anything else
+/// runs, since threads and children spawned beforehand wouldn't be traced.
+///
+/// # Safety
+///
+/// Must only be called from a `pre_exec` hook.
+pub unsafe fn trace_me() -> io::Result<()> {
+    cvt(libc::ptrace(libc::PTRACE_TRACEME, 0, null(), null()) as c_int)?;
+    cvt(libc::raise(libc::SIGSTOP))?;
+    Ok(())
+}
+
+/// Opens the `/proc` of the supervisor's PID namespace, returning -1 on failure.
+///
+/// The sandboxed process shares the supervisor's mount namespace, so this has to happen before it
+/// resumes from [`trace_me`] and replaces the root of both with `pivot_root`.
+///
+/// # Safety
+///
+/// Must only be called by the sandbox supervisor.
+pub unsafe fn open_proc() -> c_int {
+    libc::open(
+        c"/proc".as_ptr(),
+        libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+    )
+}
+
+/// Handles a wait status reported for `tracee`, which is `pid` or one of its threads or children.
+///
+/// `proc` is the directory returned by [`open_proc`], used to measure the memory, stack and file
+/// descriptors of each process right before it exits or is killed.
+///
+/// A SIGSYS raised by the seccomp filter is intercepted before the submission can handle it: the
+/// violating syscall is recorded and the sandbox killed. Other signals are delivered as usual,
+/// apart from job control signals which would leave the submission stopped.
+///
+/// An `execve` handed over by the seccomp filter is only let through until the sandboxed process
+/// has executed its command. Later ones are violations, which either fail with `ENOSYS` or kill
+/// the sandbox like any other.
+///
+/// # Safety
+///
+/// Must only be called by the sandbox supervisor.
+pub unsafe fn resume(pid: pid_t, tracee: pid_t, status: c_int, tracer: &Tracer, proc: c_int) {
+    if !libc::WIFSTOPPED(status) {
+        return;
+    }
+
+    let shared = tracer.shared();
+    let signal = match libc::WSTOPSIG(status) {
+        _ if status >> 16 == libc::PTRACE_EVENT_EXIT => {
+            if let Some(status) = read_status(proc, tracee) {
+                record_usage(shared, proc, tracee, &status);
+            }
+            0
+        }
+        _ if status >> 16 == libc::PTRACE_EVENT_EXEC => {
+            if tracee == pid {
+                shared.executed.store(true, Ordering::SeqCst);
+            }
+            0
+        }
+        _ if status >> 16 == libc::PTRACE_EVENT_SECCOMP => {
+            if tracee != pid || shared.executed.load(Ordering::SeqCst) {
+                let mut data = 0 as libc::c_ulong;
+                libc::ptrace(libc::PTRACE_GETEVENTMSG, tracee, null(), &mut data);
+
+                if data == TRACE_EXEC_KILL as libc::c_ulong {
+                    let _ = shared.violation.compare_exchange(
+                        NO_VIOLATION,
+                        libc::SYS_execve,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    );
+                    libc::kill(pid, libc::SIGKILL);
+                    return;
+                }
+
+                // A syscall number of -1 skips the syscall, which then fails with ENOSYS
+                libc::ptrace(
+                    libc::PTRACE_POKEUSER,
+                    tracee,
+                    mem::offset_of!(libc::user_regs_struct, orig_rax),
+                    -1 as libc::c_long,
+                );
+            }
+            0
+        }
+        // Clone, fork and vfork events carry no signal
+        _ if status >> 16 != 0 => 0,
+        libc::SIGSTOP => {
+            // Either the initial stop from `trace_me`, or a newly traced thread or child
+            libc::ptrace(libc::PTRACE_SETOPTIONS, tracee, null(), OPTIONS as usize);
+            0
+        }
+        libc::SIGTSTP | libc::SIGTTIN | libc::SIGTTOU => 0,
+        libc::SIGSYS => {
+            let mut info: libc::siginfo_t = mem::zeroed();
+            let result = libc::ptrace(libc::PTRACE_GETSIGINFO, tracee, null(), &mut info);
+            let info = &*(&info as *const libc::siginfo_t).cast::<SigsysInfo>();
+
+            if result == 0 && info.code == SYS_SECCOMP {
+                let _ = shared.violation.compare_exchange(
+                    NO_VIOLATION,
+                    info.syscall as i64,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                );
+                // Killing PID 1 takes down the rest of the namespace
+                libc::kill(pid, libc::SIGKILL);
+                return;
+            }
+
+            libc::SIGSYS
+        }
+        signal => signal,
+    };
+
+    if signal == libc::SIGXFSZ {
+        shared.file_size_exceeded.store(true, Ordering::SeqCst);
+    }
+
+    // PID 1 of a PID namespace ignores signals it doesn't handle. While it's traced this even
+    // includes signals raised by faults, so it would fault again forever: it's killed instead,
+    // and the supervisor reports the signal as the cause of death.
+    if signal != 0 && terminates(signal) {
+        if let Some(status) = read_status(proc, tracee) {
+            let mask = 1 << (signal - 1);
+            if status.tgid == pid as u64 && (status.caught | status.ignored) & mask == 0 {
+                record_usage(shared, proc, tracee, &status);
+                shared.fatal_signal.store(signal, Ordering::SeqCst);
+                libc::kill(pid, libc::SIGKILL);
+                return;
+            }
+        }
+    }
+
+    libc::ptrace(libc::PTRACE_CONT, tracee, null(), signal as usize);
+}
+
+/// Whether the default action of `signal` terminates the process.
+fn terminates(signal: c_int) -> bool {
+    !matches!(
+        signal,
+        libc::SIGCHLD
+            | libc::SIGCONT
+            | libc::SIGURG
+            | libc::SIGWINCH
+            | libc::SIGSTOP
+            | libc::SIGTSTP
+            | libc::SIGTTIN
+            | libc::SIGTTOU
+    )
+}
+
+/// Records the memory, stack and file descriptors of `tracee`, which are still intact while it's
+/// stopped but not after it's reaped.
+unsafe fn record_usage(shared: &Shared, proc: c_int, tracee: pid_t, status: &Status) {
+    shared
+        .peak_memory
+        .fetch_max(status.peak_memory, Ordering::SeqCst);
+    shared.peak_stack.fetch_max(status.stack, Ordering::SeqCst);
+    if let Some(open_files) = count_open_files(proc, tracee) {
+        shared
+            .peak_open_files
+            .fetch_max(open_files, Ordering::SeqCst);
+    }
+}
+
+/// The fields of `/proc/<pid>/status` used by the tracer
+struct Status {
+    /// Thread group ID, i.e. the PID of the process a thread belongs to
+    tgid: u64,
+    /// Peak resident set size (bytes)
+    peak_memory: u64,
+    /// Stack size (bytes)
+    stack: u64,
+    /// Mask of signals with a handler installed
+    caught: u64,
+    /// Mask of ignored signals
+    ignored: u64,
+}
+
+/// Reads `<tracee>/status` under `proc`, without allocating.
+unsafe fn read_status(proc: c_int, tracee: pid_t) -> Option<Status> {
+    let fd = libc::openat(
+        proc,
+        proc_path(tracee, b"status").as_ptr().cast(),
+        libc::O_RDONLY | libc::O_CLOEXEC,
+    );
+    if fd < 0 {
+        return None;
+    }
+
+    let mut buf = [0u8; 4096];
+    let mut filled = 0;
+    while filled < buf.len() {
+        let read = libc::read(fd, buf[filled..].as_mut_ptr().cast(), buf.len() - filled);
+        if read <= 0 {
+            break;
+        }
+        filled += read as usize;
+    }
+    libc::close(fd);
+
+    let field = |name: &[u8], radix: u64| {
+        let value = buf[..filled]
+            .split(|&b| b == b'\n')
+            .find_map(|line| line.strip_prefix(name))?;
+        let digits = value
+            .iter()
+            .filter_map(|&b| (b as char).to_digit(radix as u32));
+        Some(digits.fold(0, |acc, digit| acc * radix + digit as u64))
+    };
+
+    Some(Status {
+        tgid: field(b"Tgid:", 10)?,
+        // Memory isn't reported for zombie threads
+        peak_memory: field(b"VmHWM:", 10).unwrap_or(0) * 1024,
+        stack: field(b"VmStk:", 10).unwrap_or(0) * 1024,
+        caught: field(b"SigCgt:", 16)?,
+        ignored: field(b"SigIgn:", 16)?,
+    })
+}
+
+/// Counts the entries of `<tracee>/fd` under `proc`, without allocating.
+unsafe fn count_open_files(proc: c_int, tracee: pid_t) -> Option<u64> {
+    let fd = libc::openat(
+        proc,
+        proc_path(tracee, b"fd").as_ptr().cast(),
+        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+    );
+    if fd < 0 {
+        return None;
+    }
+
+    // Directory entries are 8-byte aligned
+    let mut buf = [0u64; 512];
+    let mut count = 0;
+    loop {
+        let read = libc::syscall(
+            libc::SYS_getdents64,
+            fd,
+            buf.as_mut_ptr(),
+            mem::size_of_val(&buf),
+        );
+        if read <= 0 {
+            break;
+        }
+
+        let mut offset = 0;
+        while offset < read as usize {
+            let entry = buf
+                .as_ptr()
+                .cast::<u8>()
+                .add(offset)
+                .cast::<libc::dirent64>();
+            // Skip `.` and `..`
+            if (*entry).d_name[0] != b'.' as libc::c_char {
+                count += 1;
+            }
+            offset += (*entry).d_reclen as usize;
+        }
+    }
+    libc::close(fd);
+
+    Some(count)
+}
+
+/// Builds the NUL-terminated path `<tracee>/<file>`, relative to `/proc`.
+fn proc_path(tracee: pid_t, file: &[u8]) -> [u8; 32] {
+    let mut digits = [0; 10];
+    let mut start = digits.len();
+    let mut n = tracee as u32;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+
+    let mut path = [0; 32];
+    let mut len = 0;
+    for part in [&digits[start..], b"/", file] {
+        path[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    }
+    path
+}
+
+/// Null `addr` argument, since `ptrace` is variadic and integer literals would be passed as `int`
+fn null() -> *mut c_void {
+    ptr::null_mut()
+}
@@ -0,0 +1,605 @@
+use std::{
+    ffi::CString,
+    fs::File,
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, process::ExitStatusExt},
+    },
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use libc::{c_char, c_int, pid_t};
+use tokio::net::unix::pipe;
+
+use super::{
+    cgroup, cpu, cvt, filesystem::Filesystem, landlock, namespace, namespace::Namespaces, perf,
+    resource, slot, trace::Tracer, Process, ResourceLimits, ResourceUsage, SeccompProgram,
+    TimeMeasurement,
+};
+
+/// Where the zygote keeps its end of the request socket
+const SOCKET: RawFd = 3;
+/// Where the zygote keeps the Landlock ruleset, if any
+const RULESET: RawFd = 4;
+/// Where the supervisor of a run keeps its instruction counter, once the ruleset isn't needed
+const COUNTER: RawFd = RULESET;
+/// Descriptors attached to a request: the stdio pipes, the reply socket, and optionally the
+/// tracer's memfd and the cgroup's `cgroup.procs`
+const MAX_FDS: usize = 6;
+
+/// A long-lived process per slot, which has already entered the namespaces and minimal root of
+/// the submission it runs.
+///
+/// Spawning a run from the judge means forking it, creating its namespaces and assembling its
+/// root. A zygote only has to fork and enter a fresh PID namespace, then the run restricts itself
+/// with rlimits, Landlock and seccomp before executing. Runs are requested over a socket, with
+/// their pipes attached, and each reports its exit status and resource usage back over a socket
+/// of its own.
+///
+/// The zygote is forked from the judge without executing anything, so plain data is sent over
+/// these sockets as is.
+#[derive(Debug)]
+pub struct Zygote {
+    dir: PathBuf,
+    command: Vec<String>,
+    seccomp: SeccompProgram,
+    toolchain: Vec<PathBuf>,
+    output_file: Option<PathBuf>,
+    socket: OwnedFd,
+    /// Supervisor of the zygote, which exits along with it once `socket` is closed
+    supervisor: pid_t,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Request {
+    resource_limits: ResourceLimits,
+    cpu: Option<usize>,
+    tracer: bool,
+    cgroup: bool,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+enum Reply {
+    /// Sent by the run's supervisor once it has been forked, revealing its PID
+    Started,
+    /// The run couldn't be set up or executed
+    Failed(c_int),
+    Exited {
+        status: c_int,
+        rusage: libc::rusage,
+        instructions: Option<u64>,
+    },
+}
+
+/// Arguments of the command, prepared before forking since the zygote can't allocate
+struct Argv {
+    _args: Vec<CString>,
+    pointers: Vec<*const c_char>,
+}
+
+impl Zygote {
+    /// Forks a zygote for running `command` in `dir` as the user `id`, with the minimal root
+    /// of `toolchain` and the seccomp policy `seccomp`. Runs may only write `output_file` if
+    /// given, which must already exist in `dir`.
+    pub fn spawn(
+        dir: &Path,
+        command: &[String],
+        seccomp: &SeccompProgram,
+        toolchain: &[PathBuf],
+        output_file: Option<&Path>,
+        id: Option<u32>,
+    ) -> io::Result<Self> {
+        if command.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "empty command"));
+        }
+
+        let (socket, zygote_socket) = socket_pair()?;
+
+        let args = command
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut pointers = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+        pointers.push(std::ptr::null());
+        let argv = Argv {
+            _args: args,
+            pointers,
+        };
+
+        let workdir = CString::new(dir.as_os_str().as_bytes())?;
+        let null = File::options().read(true).write(true).open("/dev/null")?;
+        let namespaces = Namespaces::new(id);
+        let filesystem = Filesystem::with_root(dir, toolchain)?;
+        let ruleset = landlock::ruleset(dir, toolchain, output_file)?;
+
+        // Forked directly rather than spawned as a command, since it never executes anything
+        let supervisor = cvt(unsafe { libc::fork() })?;
+        if supervisor == 0 {
+            unsafe {
+                let error = (|| {
+                    // Start from the signal handling of a fresh process, which runs inherit.
+                    // The standard library ignores SIGPIPE.
+                    let mut set = mem::zeroed();
+                    libc::sigemptyset(&mut set);
+                    cvt(libc::pthread_sigmask(
+                        libc::SIG_SETMASK,
+                        &set,
+                        std::ptr::null_mut(),
+                    ))?;
+                    libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+
+                    for fd in 0..3 {
+                        cvt(libc::dup2(null.as_raw_fd(), fd))?;
+                    }
+                    cvt(libc::chdir(workdir.as_ptr()))?;
+
+                    if let Some(id) = id {
+                        slot::switch_user(id)?;
+                    }
+
+                    namespaces.enter(None)?;
+
+                    filesystem.enter()?;
+                    if let Some(ruleset) = &ruleset {
+                        landlock::allow_private_mounts(ruleset.as_raw_fd())?;
+                    }
+
+                    serve(
+                        zygote_socket.as_raw_fd(),
+                        ruleset.as_ref().map(AsRawFd::as_raw_fd),
+                        &argv,
+                        seccomp,
+                        output_file.is_some(),
+                    )
+                })()
+                .unwrap_err();
+
+                let reply = Reply::Failed(error.raw_os_error().unwrap_or(0));
+                let _ = send(zygote_socket.as_raw_fd(), &reply, &[]);
+                libc::_exit(127);
+            }
+        }
+        drop(zygote_socket);
+
+        let zygote = Zygote {
+            dir: dir.to_owned(),
+            command: command.to_owned(),
+            seccomp: seccomp.clone(),
+            toolchain: toolchain.to_owned(),
+            output_file: output_file.map(Path::to_owned),
+            socket,
+            supervisor,
+        };
+
+        // Reaped on drop if it failed to set up
+        match unsafe { receive::<Reply>(zygote.socket.as_raw_fd(), &mut [])? } {
+            Some((Reply::Started, _)) => Ok(zygote),
+            Some((Reply::Failed(errno), _)) => Err(io::Error::from_raw_os_error(errno)),
+            _ => Err(io::Error::other("zygote exited before serving")),
+        }
+    }
+
+    /// Whether this zygote runs `command` in `dir` with the same policy.
+    pub fn serves(
+        &self,
+        dir: &Path,
+        command: &[String],
+        seccomp: &SeccompProgram,
+        toolchain: &[PathBuf],
+        output_file: Option<&Path>,
+    ) -> bool {
+        self.dir == dir
+            && self.command == command
+            && self.seccomp == *seccomp
+            && self.toolchain == toolchain
+            && self.output_file.as_deref() == output_file
+    }
+
+    /// Forks a run, which joins the cgroup of `cgroup_procs` and is pinned to `cpu` if given.
+    pub async fn start(
+        &self,
+        resource_limits: ResourceLimits,
+        cgroup_procs: Option<RawFd>,
+        cpu: Option<usize>,
+        tracer: Option<&Tracer>,
+    ) -> io::Result<(Process<pipe::Sender, pipe::Receiver, pipe::Receiver>, Run)> {
+        let (stdin_read, stdin_write) = pipe_pair()?;
+        let (stdout_read, stdout_write) = pipe_pair()?;
+        let (stderr_read, stderr_write) = pipe_pair()?;
+        let (reply, run_reply) = socket_pair()?;
+        // Reveals the PID of the run's supervisor in the judge's PID namespace
+        cvt(unsafe {
+            libc::setsockopt(
+                reply.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                (&1 as *const c_int).cast(),
+                mem::size_of::<c_int>() as u32,
+            )
+        })?;
+
+        let request = Request {
+            resource_limits,
+            cpu,
+            tracer: tracer.is_some(),
+            cgroup: cgroup_procs.is_some(),
+        };
+        let fds = [
+            stdin_read.as_raw_fd(),
+            stdout_write.as_raw_fd(),
+            stderr_write.as_raw_fd(),
+            run_reply.as_raw_fd(),
+        ]
+        .into_iter()
+        .chain(tracer.map(Tracer::memfd))
+        .chain(cgroup_procs)
+        .collect::<Vec<_>>();
+        unsafe { send(self.socket.as_raw_fd(), &request, &fds)? };
+        drop((stdin_read, stdout_write, stderr_write, run_reply));
+
+        let (reply, pid) = tokio::task::spawn_blocking(move || {
+            match unsafe { receive::<Reply>(reply.as_raw_fd(), &mut [])? } {
+                Some((Reply::Started, Some(pid))) => Ok((reply, pid)),
+                Some((Reply::Failed(errno), _)) => Err(io::Error::from_raw_os_error(errno)),
+                _ => Err(io::Error::other("zygote failed to start run")),
+            }
+        })
+        .await??;
+
+        let process = Process {
+            pid,
+            stdin: pipe::Sender::from_owned_fd(stdin_write)?,
+            stdout: pipe::Receiver::from_owned_fd(stdout_read)?,
+            stderr: pipe::Receiver::from_owned_fd(stderr_read)?,
+        };
+        Ok((process, Run { reply }))
+    }
+}
+
+impl Drop for Zygote {
+    fn drop(&mut self) {
+        // The zygote can't be reached anymore, and killing its supervisor takes down its whole
+        // namespace along with any run left behind
+        unsafe {
+            libc::kill(self.supervisor, libc::SIGKILL);
+            libc::waitpid(self.supervisor, &mut 0, 0);
+        }
+    }
+}
+
+/// A run forked from a zygote.
+#[derive(Debug)]
+pub struct Run {
+    reply: OwnedFd,
+}
+
+impl Run {
+    /// Waits for the run to exit, also returning the instructions it retired if they were
+    /// counted.
+    pub fn wait(self) -> io::Result<(ExitStatus, ResourceUsage, Option<u64>)> {
+        let mut error = None;
+
+        loop {
+            match unsafe { receive::<Reply>(self.reply.as_raw_fd(), &mut [])? } {
+                Some((
+                    Reply::Exited {
+                        status,
+                        rusage,
+                        instructions,
+                    },
+                    _,
+                )) => {
+                    return match error {
+                        Some(errno) => Err(io::Error::from_raw_os_error(errno)),
+                        None => Ok((
+                            ExitStatus::from_raw(status),
+                            resource::usage(&rusage),
+                            instructions,
+                        )),
+                    };
+                }
+                Some((Reply::Failed(errno), _)) => error = Some(errno),
+                Some((Reply::Started, _)) => {}
+                None => {
+                    return Err(match error {
+                        Some(errno) => io::Error::from_raw_os_error(errno),
+                        None => io::Error::other("run supervisor exited without reporting"),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Serves requests on `socket` until it's closed, forking a run for each.
+unsafe fn serve(
+    socket: RawFd,
+    ruleset: Option<RawFd>,
+    argv: &Argv,
+    seccomp: &SeccompProgram,
+    output_file: bool,
+) -> io::Result<()> {
+    // Release every descriptor inherited from the judge, including pipes of unrelated runs
+    let socket = cvt(libc::fcntl(socket, libc::F_DUPFD, RULESET + 1))?;
+    let ruleset = match ruleset {
+        Some(fd) => Some(cvt(libc::fcntl(fd, libc::F_DUPFD, RULESET + 1))?),
+        None => None,
+    };
+    cvt(libc::dup2(socket, SOCKET))?;
+    if let Some(ruleset) = ruleset {
+        cvt(libc::dup2(ruleset, RULESET))?;
+    }
+    libc::close_range(RULESET as u32 + 1, u32::MAX, 0);
+    let ruleset = ruleset.map(|_| RULESET);
+
+    send(SOCKET, &Reply::Started, &[])?;
+
+    // The zygote is PID 1 of its namespace, and isn't interested in the status of any process
+    libc::signal(libc::SIGCHLD, libc::SIG_IGN);
+
+    loop {
+        let mut fds = [-1; MAX_FDS];
+        let request = match receive::<Request>(SOCKET, &mut fds) {
+            Ok(Some((request, _))) => request,
+            Ok(None) => libc::_exit(0),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => libc::_exit(1),
+        };
+
+        match libc::fork() {
+            0 => start(request, fds, ruleset, argv, seccomp, output_file),
+            -1 => {
+                let reply = Reply::Failed(*libc::__errno_location());
+                let _ = send(fds[3], &reply, &[]);
+            }
+            _ => {}
+        }
+
+        for fd in fds.into_iter().filter(|&fd| fd >= 0) {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Sets up and supervises a run in the process forked for it, then reports how it exited.
+unsafe fn start(
+    request: Request,
+    fds: [RawFd; MAX_FDS],
+    ruleset: Option<RawFd>,
+    argv: &Argv,
+    seccomp: &SeccompProgram,
+    output_file: bool,
+) -> ! {
+    let [stdin, stdout, stderr, reply, ..] = fds;
+    let mut rest = fds[4..].iter().copied();
+    let tracer_fd = if request.tracer { rest.next() } else { None };
+    let cgroup_procs = if request.cgroup { rest.next() } else { None };
+
+    // Never dropped, since its memfd is closed along with every other descriptor below
+    let mut tracer = None;
+
+    let result = (|| -> io::Result<_> {
+        libc::signal(libc::SIGCHLD, libc::SIG_DFL);
+        send(reply, &Reply::Started, &[])?;
+
+        if let Some(fd) = tracer_fd {
+            tracer = Some(Tracer::map(OwnedFd::from_raw_fd(fd))?);
+        }
+        if let Some(fd) = cgroup_procs {
+            cgroup::join(fd)?;
+        }
+        if let Some(cpu) = request.cpu {
+            cpu::pin(cpu)?;
+        }
+
+        cvt(libc::dup2(stdin, 0))?;
+        cvt(libc::dup2(stdout, 1))?;
+        cvt(libc::dup2(stderr, 2))?;
+
+        // Inherited by the run, which is only counted once it executes the command
+        let counter = match request.resource_limits.time_measurement {
+            TimeMeasurement::Cpu => None,
+            TimeMeasurement::Instructions { .. } => perf::counter().ok(),
+        };
+
+        let pid = namespace::enter_pid(tracer.as_ref())?;
+        if pid == 0 {
+            let mut request = request;
+            if counter.is_some() {
+                // The limit is checked against the instructions instead, so only the wall-clock
+                // limit kills the run
+                request.resource_limits.cpu = request.resource_limits.wall_time().as_secs();
+            }
+
+            let error = exec(request, ruleset, argv, seccomp, output_file).unwrap_err();
+            let _ = send(
+                reply,
+                &Reply::Failed(error.raw_os_error().unwrap_or(0)),
+                &[],
+            );
+            libc::_exit(127);
+        }
+
+        // Release everything but the reply socket and the counter, so that the judge observes
+        // EOF on the pipes once the run exits
+        cvt(libc::dup2(reply, SOCKET))?;
+        let counter = match counter {
+            Some(counter) => Some(cvt(libc::dup2(counter.into_raw_fd(), COUNTER))?),
+            None => None,
+        };
+        libc::close_range(0, SOCKET as u32 - 1, 0);
+        libc::close_range(counter.unwrap_or(SOCKET) as u32 + 1, u32::MAX, 0);
+
+        let status = namespace::wait(pid, tracer.as_ref());
+        let mut rusage = mem::zeroed();
+        libc::getrusage(libc::RUSAGE_CHILDREN, &mut rusage);
+        // Every process of the run has been reaped along with it, adding its count
+        let instructions = counter.and_then(|counter| perf::read(&counter).ok());
+        Ok(Reply::Exited {
+            status,
+            rusage,
+            instructions,
+        })
+    })();
+
+    let (reply_fd, reply) = match result {
+        Ok(reply) => (SOCKET, reply),
+        Err(e) => (reply, Reply::Failed(e.raw_os_error().unwrap_or(0))),
+    };
+    let _ = send(reply_fd, &reply, &[]);
+    libc::_exit(0)
+}
+
+/// Restricts the run and executes its command, only returning on failure.
+unsafe fn exec(
+    request: Request,
+    ruleset: Option<RawFd>,
+    argv: &Argv,
+    seccomp: &SeccompProgram,
+    output_file: bool,
+) -> io::Result<()> {
+    // Still usable to report failures, but not by the command
+    libc::close_range(3, u32::MAX, libc::CLOSE_RANGE_CLOEXEC as i32);
+
+    if let Some(ruleset) = ruleset {
+        landlock::restrict_self(ruleset)?;
+    }
+
+    let resource_limits = request.resource_limits;
+    resource_limits.set()?;
+    resource_limits.set_memory(request.cgroup)?;
+    if !request.cgroup {
+        resource_limits.set_processes()?;
+    }
+
+    seccomp
+        .apply(output_file, request.tracer)
+        .map_err(|e| io::Error::other(format!("seccomp failed: {e}")))?;
+
+    libc::execvp(argv.pointers[0], argv.pointers.as_ptr());
+    Err(io::Error::last_os_error())
+}
+
+fn socket_pair() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    cvt(unsafe {
+        libc::socketpair(
+            libc::AF_UNIX,
+            libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+            0,
+            fds.as_mut_ptr(),
+        )
+    })?;
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Creates a pipe, returning its read and write ends.
+fn pipe_pair() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    cvt(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })?;
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Sends `message` over `socket`, with `fds` attached.
+unsafe fn send<T: Copy>(socket: RawFd, message: &T, fds: &[RawFd]) -> io::Result<()> {
+    if fds.is_empty() {
+        // Unlike sendmsg(), write() is allowed by any seccomp policy that allows output at all,
+        // so runs can still report failing to execute
+        let written = libc::write(socket, (message as *const T).cast(), mem::size_of::<T>());
+        return if written < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+    }
+
+    let mut iov = libc::iovec {
+        iov_base: (message as *const T).cast_mut().cast(),
+        iov_len: mem::size_of::<T>(),
+    };
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    let len = mem::size_of_val(fds) as u32;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = libc::CMSG_SPACE(len) as usize;
+
+    let cmsg = libc::CMSG_FIRSTHDR(&msg);
+    (*cmsg).cmsg_level = libc::SOL_SOCKET;
+    (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+    (*cmsg).cmsg_len = libc::CMSG_LEN(len) as usize;
+    std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg).cast(), fds.len());
+
+    if libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Receives a message sent with [`send`] from `socket`, storing attached descriptors in `fds`,
+/// or returns `None` once the other end is closed. Also returns the PID of the sender if the
+/// socket passes credentials.
+unsafe fn receive<T: Copy>(
+    socket: RawFd,
+    fds: &mut [RawFd],
+) -> io::Result<Option<(T, Option<pid_t>)>> {
+    let mut message = mem::MaybeUninit::<T>::uninit();
+    let mut iov = libc::iovec {
+        iov_base: message.as_mut_ptr().cast(),
+        iov_len: mem::size_of::<T>(),
+    };
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = mem::zeroed();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control);
+
+    let received = libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC);
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    } else if received == 0 {
+        return Ok(None);
+    }
+
+    let mut pid = None;
+    let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+    while !cmsg.is_null() {
+        let data = libc::CMSG_DATA(cmsg);
+        match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+            (libc::SOL_SOCKET, libc::SCM_RIGHTS) => {
+                let count = ((*cmsg).cmsg_len - libc::CMSG_LEN(0) as usize) / 4;
+                for i in 0..count {
+                    let fd = data.cast::<RawFd>().add(i).read_unaligned();
+                    match fds.get_mut(i) {
+                        Some(slot) => *slot = fd,
+                        None => {
+                            libc::close(fd);
+                        }
+                    }
+                }
+            }
+            (libc::SOL_SOCKET, libc::SCM_CREDENTIALS) => {
+                pid = Some(data.cast::<libc::ucred>().read_unaligned().pid);
+            }
+            _ => {}
+        }
+        cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+    }
+
+    if received as usize != mem::size_of::<T>() || msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "truncated zygote message",
+        ));
+    }
+
+    Ok(Some((message.assume_init(), pid)))
+}
@@ -1,48 +1,126 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    str,
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::response::sse::Event;
-use color_eyre::eyre::WrapErr;
+use color_eyre::eyre::{bail, eyre, WrapErr};
 use schemars::JsonSchema;
 use serde::Serialize;
 use tokio::{
-    sync::{watch, Semaphore},
+    fs, io,
+    sync::{watch, Mutex},
     task::JoinSet,
 };
+use uuid::Uuid;
 use yansi::Paint;
 
 use crate::{
-    contest::{Config, Language, Task, Test},
-    sandbox::{run, Output, Profile, ResourceUsage},
+    contest::{Checker, Config, Contest, Io, Language, Scoring, Task, Test},
+    sandbox::{
+        Output, Profile, ResourceLimits, ResourceUsage, Sandbox, Sink, Slot, SlotPool, Stdin,
+        Streams, Syscall, TimeMeasurement,
+    },
 };
 
-const MAX_CONCURRENT_SUBMISSIONS: usize = 5;
-static RATE_LIMIT: Semaphore = Semaphore::const_new(MAX_CONCURRENT_SUBMISSIONS);
+/// Bytes buffered in each direction between a submission and its interactor
+const INTERACTION_BUFFER: usize = 64 << 10;
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 pub struct Report {
     task: Verdict,
     subtasks: Vec<Verdict>,
     tests: Vec<Vec<TestReport>>,
+    /// Points awarded for each subtask, including the points of its tests
+    subtask_scores: Vec<f64>,
+    /// Points awarded for the task, the sum of `subtask_scores`
+    score: f64,
+}
+
+impl Report {
+    /// Scores the `tests` of each subtask according to `scoring`: every test is worth
+    /// `test-score` and every subtask `subtask-score`, times the fraction awarded.
+    fn new(
+        task: Verdict,
+        subtasks: Vec<Verdict>,
+        tests: Vec<Vec<TestReport>>,
+        scoring: &Scoring,
+    ) -> Self {
+        // Each subtask is only worth as much as its worst test
+        let subtask_scores = tests
+            .iter()
+            .map(|tests| {
+                let lowest = tests.iter().map(|test| test.score).reduce(f64::min);
+                let total = tests.iter().map(|test| test.score).sum::<f64>();
+                lowest.unwrap_or(0.0) * scoring.subtask_score as f64
+                    + total * scoring.test_score as f64
+            })
+            .collect::<Vec<_>>();
+
+        Report {
+            task,
+            subtasks,
+            tests,
+            score: subtask_scores.iter().sum(),
+            subtask_scores,
+        }
+    }
+
+    /// A report of an answer, which is worth `answer-score` if accepted.
+    fn answer(verdict: Verdict, scoring: &Scoring) -> Self {
+        Report {
+            task: verdict,
+            subtasks: vec![],
+            tests: vec![],
+            subtask_scores: vec![],
+            score: if verdict == Verdict::Accepted {
+                scoring.answer_score as f64
+            } else {
+                0.0
+            },
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 pub struct TestReport {
     verdict: Verdict,
     resource_usage: ResourceUsage,
+    /// How the CPU time in `resource_usage` was measured, which is `cpu` if instructions
+    /// couldn't be counted. With `instructions` it's all user time.
+    time_measurement: TimeMeasurement,
+    /// The forbidden syscall made by a submission judged as a security violation
+    security_violation: Option<Syscall>,
+    /// Fraction of the test's score awarded, which the interactor or checker of the task may only
+    /// award in part
+    score: f64,
+    /// What the checker of the task reported about the output
+    message: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
 pub enum Verdict {
     CompileError,
+    SecurityViolation,
     RuntimeError,
     WrongAnswer,
+    PresentationError,
     TimeLimitExceeded,
+    IdlenessLimitExceeded,
     MemoryLimitExceeded,
+    OutputLimitExceeded,
+    ProcessLimitExceeded,
+    StackLimitExceeded,
+    FileSizeLimitExceeded,
+    OpenFileLimitExceeded,
     Skipped,
     Accepted,
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum Message {
     /// Queued for submission
@@ -50,7 +128,11 @@ pub enum Message {
     /// Indicates that the compile step has been started (optional)
     Compiling,
     /// Provides compiler warnings and errors (optional)
-    Compiled { exit_code: i32, stderr: String },
+    Compiled {
+        exit_code: i32,
+        stderr: String,
+        timed_out: bool,
+    },
     /// Judging status
     Judging { verdict: Verdict },
     /// Tests were skipped due to exceeding resource usage
@@ -68,59 +150,91 @@ struct State {
     tx: Sender,
     dir: Arc<Path>,
     config: &'static Config,
+    scoring: &'static Scoring,
     task: &'static Task,
     language: &'static Language,
+    slot: Arc<Slot<'static>>,
+    sandbox: &'static dyn Sandbox,
+    /// Held while running a test of a task with file I/O, since every test uses the same files
+    files: Arc<Mutex<()>>,
 }
 
 impl State {
-    async fn run(&self, profile: Profile, stdin: &[u8]) -> tokio::io::Result<Output> {
-        let command = match profile {
-            Profile::Compile => self
+    async fn run(
+        &self,
+        dir: &Path,
+        profile: Profile,
+        streams: Streams<'_>,
+    ) -> tokio::io::Result<Output> {
+        let command = match &profile {
+            Profile::Compile(_) => self
                 .language
                 .compile
                 .as_ref()
                 .expect("attempted to execute non-existent compile command"),
-            Profile::Run(_) => &self.language.run,
+            Profile::Check(_) => unreachable!("checkers are executed by the sandbox directly"),
+            Profile::Run(..) => &self.language.run,
+            Profile::Interact(_) => {
+                &self
+                    .task
+                    .interactor
+                    .as_ref()
+                    .expect("attempted to execute non-existent interactor")
+                    .command
+            }
         };
 
-        run(&self.dir, command, stdin, profile).await
+        self.sandbox
+            .run(dir, command, streams, profile, &self.slot)
+            .await
     }
 
     async fn send(&self, message: Message) {
-        if let Err(e) = self
-            .tx
-            .send(Ok(Event::default().json_data(message).unwrap()))
-            .await
-        {
-            tracing::error!("failed to send message: {e}");
-        }
+        send(&self.tx, message).await;
+    }
+}
+
+async fn send(tx: &Sender, message: Message) {
+    if let Err(e) = tx
+        .send(Ok(Event::default().json_data(message).unwrap()))
+        .await
+    {
+        tracing::error!("failed to send message: {e}");
     }
 }
 
+/// Judges the submission in `dir` to `task` of `contest` in a slot of `slots`, which is held until
+/// it's judged.
 #[tracing::instrument(skip_all)]
 pub async fn submit(
     tx: Sender,
     dir: impl AsRef<Path>,
-    config: &'static Config,
+    contest: &'static Contest,
     task: &'static Task,
     language: &'static Language,
+    sandbox: &'static dyn Sandbox,
+    slots: &'static SlotPool,
 ) {
+    send(
+        &tx,
+        Message::Queued {
+            tests: task.subtasks.iter().map(|s| s.tests.len() as u32).sum(),
+        },
+    )
+    .await;
+
     let state = State {
         tx,
         dir: Arc::from(dir.as_ref()),
-        config,
+        config: &contest.config,
+        scoring: &contest.scoring,
         task,
         language,
+        slot: Arc::new(slots.acquire().await),
+        sandbox,
+        files: Arc::new(Mutex::new(())),
     };
 
-    state
-        .send(Message::Queued {
-            tests: task.subtasks.iter().map(|s| s.tests.len() as u32).sum(),
-        })
-        .await;
-
-    let _permit = RATE_LIMIT.acquire().await.expect("semaphore closed");
-
     if let Err(report) = submit_inner(state.clone()).await {
         tracing::error!("{report:?}");
 
@@ -133,16 +247,45 @@ pub async fn submit(
     }
 }
 
+/// Judges an `answer` to `task`, which must accept answers.
+#[tracing::instrument(skip_all)]
+pub async fn submit_answer(
+    tx: Sender,
+    scoring: &'static Scoring,
+    task: &'static Task,
+    answer: String,
+) {
+    let accepted = task
+        .answer
+        .as_ref()
+        .expect("attempted to judge answer to task without one")
+        .accepts(&answer, task.answer_compare);
+    let verdict = if accepted {
+        Verdict::Accepted
+    } else {
+        Verdict::WrongAnswer
+    };
+    tracing::trace!("answer judged as {verdict:?}");
+
+    let report = Report::answer(verdict, scoring);
+    send(&tx, Message::Done { report }).await;
+}
+
 async fn submit_inner(state: State) -> color_eyre::Result<()> {
+    state
+        .slot
+        .chown(&state.dir)
+        .wrap_err("failed to hand submission over to its slot")?;
+
     if state.language.compile.is_some() {
         if !compile(state.clone())
             .await
             .wrap_err("failed to compile submission")?
         {
-            let report = Report {
-                task: Verdict::CompileError,
-                subtasks: vec![Verdict::CompileError; state.task.subtasks.len()],
-                tests: state
+            let report = Report::new(
+                Verdict::CompileError,
+                vec![Verdict::CompileError; state.task.subtasks.len()],
+                state
                     .task
                     .subtasks
                     .iter()
@@ -150,13 +293,18 @@ async fn submit_inner(state: State) -> color_eyre::Result<()> {
                         vec![
                             TestReport {
                                 verdict: Verdict::CompileError,
-                                resource_usage: ResourceUsage::default()
+                                resource_usage: ResourceUsage::default(),
+                                time_measurement: TimeMeasurement::Cpu,
+                                security_violation: None,
+                                score: 0.0,
+                                message: None,
                             };
                             s.tests.len()
                         ]
                     })
                     .collect(),
-            };
+                state.scoring,
+            );
 
             state.send(Message::Done { report }).await;
             return Ok(());
@@ -177,12 +325,27 @@ async fn submit_inner(state: State) -> color_eyre::Result<()> {
 async fn compile(state: State) -> color_eyre::Result<bool> {
     state.send(Message::Compiling).await;
 
+    let resource_limits = state
+        .language
+        .compile_resource_limits
+        .unwrap_or(state.config.compile_resource_limits);
     let output = state
-        .run(Profile::Compile, &[])
+        .run(
+            &state.dir,
+            Profile::Compile(resource_limits),
+            Streams::new(&[][..]),
+        )
         .await
         .wrap_err("failed to execute compile command")?;
     let status = output.exit_status();
     let exit_code = status.code().unwrap_or(-1);
+    // Killed either for its wall-clock time or, at the soft CPU limit, for its CPU time
+    let timed_out = output.timed_out()
+        || !status.success()
+            && (status.signal() == Some(libc::SIGXCPU)
+                || output.resource_usage().exceeded_time(resource_limits));
+    // The compiler's output may have been truncated in the middle of a character
+    let stderr = String::from_utf8_lossy(output.stderr()).into_owned();
 
     if status.success() {
         if !output.stderr().is_empty() {
@@ -190,7 +353,8 @@ async fn compile(state: State) -> color_eyre::Result<bool> {
             state
                 .send(Message::Compiled {
                     exit_code,
-                    stderr: output.stderr_utf8().unwrap_or_default().to_owned(),
+                    stderr,
+                    timed_out,
                 })
                 .await;
         }
@@ -201,11 +365,14 @@ async fn compile(state: State) -> color_eyre::Result<bool> {
         state
             .send(Message::Compiled {
                 exit_code,
-                stderr: output.stderr_utf8().unwrap_or_default().to_owned(),
+                stderr,
+                timed_out,
             })
             .await;
 
-        if exit_code != -1 {
+        if timed_out {
+            tracing::error!("compilation timed out");
+        } else if exit_code != -1 {
             tracing::error!("compilation failed (exit code: {exit_code})");
         } else {
             tracing::error!("compilation failed (terminated by signal)");
@@ -238,10 +405,26 @@ async fn judge(state: State) -> color_eyre::Result<Report> {
                         test_idx + 1,
                         match test_report.verdict {
                             Verdict::CompileError => Paint::yellow("Compile Error"),
+                            Verdict::SecurityViolation => Paint::red("Security Violation"),
                             Verdict::RuntimeError => Paint::yellow("Runtime Error"),
                             Verdict::MemoryLimitExceeded => Paint::magenta("Memory Limit Exceeded"),
                             Verdict::TimeLimitExceeded => Paint::magenta("Time Limit Exceeded"),
+                            Verdict::IdlenessLimitExceeded => {
+                                Paint::magenta("Idleness Limit Exceeded")
+                            }
+                            Verdict::OutputLimitExceeded => Paint::magenta("Output Limit Exceeded"),
+                            Verdict::ProcessLimitExceeded => {
+                                Paint::magenta("Process Limit Exceeded")
+                            }
+                            Verdict::StackLimitExceeded => Paint::magenta("Stack Limit Exceeded"),
+                            Verdict::FileSizeLimitExceeded => {
+                                Paint::magenta("File Size Limit Exceeded")
+                            }
+                            Verdict::OpenFileLimitExceeded => {
+                                Paint::magenta("Open File Limit Exceeded")
+                            }
                             Verdict::WrongAnswer => Paint::red("Wrong Answer"),
+                            Verdict::PresentationError => Paint::red("Presentation Error"),
                             Verdict::Skipped => Paint::blue("Skipped"),
                             Verdict::Accepted => Paint::green("Accepted"),
                         }
@@ -262,7 +445,11 @@ async fn judge(state: State) -> color_eyre::Result<Report> {
             let mut subtask_reports = vec![
                 TestReport {
                     verdict: Verdict::Skipped,
-                    resource_usage: ResourceUsage::default()
+                    resource_usage: ResourceUsage::default(),
+                    time_measurement: TimeMeasurement::Cpu,
+                    security_violation: None,
+                    score: 0.0,
+                    message: None,
                 };
                 subtask.tests.len()
             ];
@@ -290,20 +477,23 @@ async fn judge(state: State) -> color_eyre::Result<Report> {
         });
     }
 
-    let mut report = Report {
-        task: Verdict::Accepted,
-        subtasks: vec![Verdict::Accepted; state.task.subtasks.len()],
-        tests: vec![vec![]; state.task.subtasks.len()],
-    };
+    let mut task_verdict = Verdict::Accepted;
+    let mut subtask_verdicts = vec![Verdict::Accepted; state.task.subtasks.len()];
+    let mut test_reports = vec![vec![]; state.task.subtasks.len()];
 
     while let Some(result) = subtask_set.join_next().await {
         let (subtask_idx, subtask_verdict, subtask_reports) = result??;
-        report.task = report.task.min(subtask_verdict);
-        report.subtasks[subtask_idx] = subtask_verdict;
-        report.tests[subtask_idx] = subtask_reports;
+        task_verdict = task_verdict.min(subtask_verdict);
+        subtask_verdicts[subtask_idx] = subtask_verdict;
+        test_reports[subtask_idx] = subtask_reports;
     }
 
-    Ok(report)
+    Ok(Report::new(
+        task_verdict,
+        subtask_verdicts,
+        test_reports,
+        state.scoring,
+    ))
 }
 
 async fn run_test(
@@ -311,35 +501,783 @@ async fn run_test(
     skip_tx: watch::Sender<u8>,
     test: &Test,
 ) -> color_eyre::Result<TestReport> {
-    let output = state
-        .run(
-            Profile::Run(state.config.resource_limits),
-            test.input.as_bytes(),
-        )
-        .await?;
+    let resource_limits = ResourceLimits {
+        processes: state
+            .language
+            .processes
+            .unwrap_or(state.config.resource_limits.processes),
+        ..state.config.resource_limits
+    };
+    let output_file = match &state.task.io {
+        Io::Stdio => None,
+        Io::File { output, .. } => Some(PathBuf::from(output)),
+    };
+    let profile = Profile::Run(
+        resource_limits,
+        state.language.seccomp_program.clone(),
+        state.language.toolchain.clone(),
+        output_file,
+    );
+    let (output, interactor, answer) = if state.task.interactor.is_some() {
+        let (output, interactor) = interact(&state, profile, test).await?;
+        (output, Some(interactor), None)
+    } else if let Io::File { input, output } = &state.task.io {
+        let (output, answer) = run_with_files(&state, profile, test, input, output).await?;
+        (output, None, Some(answer))
+    } else {
+        let stdin = Streams::new(test.input.stdin());
+        (state.run(&state.dir, profile, stdin).await?, None, None)
+    };
 
     let status = output.exit_status();
-    let resource_usage = output.resource_usage();
+    let (resource_usage, time_measurement) = match output
+        .instructions()
+        .and_then(|instructions| resource_limits.time_measurement.user_time(instructions))
+    {
+        Some(user_time) => (
+            ResourceUsage {
+                user_time,
+                sys_time: Duration::ZERO,
+                ..output.resource_usage()
+            },
+            resource_limits.time_measurement,
+        ),
+        None => (output.resource_usage(), TimeMeasurement::Cpu),
+    };
+    let security_violation = output.security_violation();
 
-    let verdict =
-        if resource_usage.exceeded(state.config.resource_limits) && status.code().is_none() {
-            skip_tx.send_modify(|count| *count += 1);
-            if resource_usage.exceeded_time(state.config.resource_limits) {
-                Verdict::TimeLimitExceeded
-            } else {
-                Verdict::MemoryLimitExceeded
+    let mut checked = None;
+    let mut interacted_score = None;
+    let verdict = if let Some(syscall) = security_violation {
+        tracing::warn!(
+            language = state.language.name,
+            syscall = syscall.number,
+            name = syscall.name,
+            "security violation"
+        );
+        Verdict::SecurityViolation
+    } else if output.output_limit_exceeded() {
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::OutputLimitExceeded
+    } else if output.memory_limit_exceeded() && !status.success() {
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::MemoryLimitExceeded
+    } else if output.process_limit_exceeded() && !status.success() {
+        // Checked first, since fork bombs usually end up killed by the wall-clock limit
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::ProcessLimitExceeded
+    } else if output.stack_limit_exceeded() {
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::StackLimitExceeded
+    } else if output.file_size_limit_exceeded() && !status.success() {
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::FileSizeLimitExceeded
+    } else if output.open_file_limit_exceeded() && !status.success() {
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::OpenFileLimitExceeded
+    } else if time_measurement != TimeMeasurement::Cpu
+        && resource_usage.exceeded_time(resource_limits)
+    {
+        // Runs timed by their instructions aren't killed once they exceed the limit
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::TimeLimitExceeded
+    } else if status.signal() == Some(libc::SIGXCPU) {
+        skip_tx.send_modify(|count| *count += 1);
+        Verdict::TimeLimitExceeded
+    } else if output.timed_out() {
+        skip_tx.send_modify(|count| *count += 1);
+        if resource_usage.exceeded_time(state.config.resource_limits) {
+            Verdict::TimeLimitExceeded
+        } else {
+            Verdict::IdlenessLimitExceeded
+        }
+    } else if resource_usage.exceeded(state.config.resource_limits) && status.code().is_none() {
+        skip_tx.send_modify(|count| *count += 1);
+        if resource_usage.exceeded_time(state.config.resource_limits) {
+            Verdict::TimeLimitExceeded
+        } else {
+            Verdict::MemoryLimitExceeded
+        }
+    } else if let Some(interactor) = &interactor {
+        let message = String::from_utf8_lossy(interactor.stderr());
+        let judged = match interactor.exit_status().code() {
+            Some(code) => testlib_verdict(code, message.trim())
+                .wrap_err("interactor awarded invalid points")?,
+            None => None,
+        };
+
+        match judged {
+            Some((Verdict::Accepted, _)) if !status.success() => Verdict::RuntimeError,
+            Some((verdict, score)) => {
+                interacted_score = score;
+                verdict
             }
-        } else if status.success() {
-            match output.stdout_utf8() {
-                Ok(stdout) if stdout.trim() == test.output.trim() => Verdict::Accepted,
-                _ => Verdict::WrongAnswer,
+            // The interactor was most likely waiting for the submission
+            None if interactor.timed_out() => {
+                skip_tx.send_modify(|count| *count += 1);
+                Verdict::IdlenessLimitExceeded
             }
+            None => bail!(
+                "interactor failed ({}): {message}",
+                interactor.exit_status()
+            ),
+        }
+    } else if status.success() {
+        let answer = answer.as_deref().unwrap_or(output.stdout());
+        if let Some(checker) = &state.task.checker {
+            let report = check(&state, checker, test, answer).await?;
+            let verdict = report.verdict;
+            checked = Some(report);
+            verdict
         } else {
-            Verdict::RuntimeError
-        };
+            let expected = test
+                .output
+                .read()
+                .await
+                .wrap_err("failed to read expected output")?;
+            if test
+                .compare
+                .unwrap_or(state.task.compare)
+                .matches(answer, &expected)
+            {
+                Verdict::Accepted
+            } else {
+                Verdict::WrongAnswer
+            }
+        }
+    } else {
+        Verdict::RuntimeError
+    };
+
+    let percentage = interacted_score.or(checked.as_ref().and_then(|checked| checked.score));
+    let score = match percentage {
+        Some(percentage) => percentage as f64 / 100.0,
+        None if verdict == Verdict::Accepted => 1.0,
+        None => 0.0,
+    };
+    let message = checked.map(|checked| checked.message);
 
     Ok(TestReport {
         verdict,
         resource_usage,
+        time_measurement,
+        security_violation,
+        score,
+        message,
+    })
+}
+
+/// Runs the submission with the test's input in the file `input`, and returns its output along
+/// with what it wrote to the file `output`.
+async fn run_with_files(
+    state: &State,
+    profile: Profile,
+    test: &Test,
+    input: &str,
+    output: &str,
+) -> color_eyre::Result<(Output, Vec<u8>)> {
+    let _files = state.files.lock().await;
+    let input = state.dir.join(input);
+    let output = state.dir.join(output);
+
+    test.input
+        .write(&input)
+        .await
+        .wrap_err("failed to write input file")?;
+    // Only an existing output file can be written, and the submission can't remove it either
+    fs::write(&output, "")
+        .await
+        .wrap_err("failed to create output file")?;
+    state
+        .slot
+        .chown(&state.dir)
+        .wrap_err("failed to hand I/O files over to the slot")?;
+
+    let result = state
+        .run(&state.dir, profile, Streams::new(&[][..]))
+        .await?;
+    let answer = fs::read(&output)
+        .await
+        .wrap_err("failed to read output file")?;
+
+    Ok((result, answer))
+}
+
+/// Runs the submission connected to the task's interactor, which is given the test's files in a
+/// directory of its own, and returns both of their outputs.
+async fn interact(
+    state: &State,
+    profile: Profile,
+    test: &Test,
+) -> color_eyre::Result<(Output, Output)> {
+    let interactor = state.task.interactor.as_ref().unwrap();
+    let resource_limits = interactor
+        .resource_limits
+        .unwrap_or(state.config.resource_limits);
+
+    // Outside of the submission's directory, so that it can't tamper with the expected output
+    let dir = state
+        .dir
+        .with_extension(format!("interactor-{}", Uuid::new_v4()));
+    fs::create_dir(&dir)
+        .await
+        .wrap_err("failed to create interactor directory")?;
+    test.input.write(&dir.join("input")).await?;
+    test.output.write(&dir.join("output")).await?;
+
+    let (to_interactor, from_submission) = io::duplex(INTERACTION_BUFFER);
+    let (to_submission, from_interactor) = io::duplex(INTERACTION_BUFFER);
+
+    let (output, interactor_output) = tokio::join!(
+        state.run(
+            &state.dir,
+            profile,
+            Streams {
+                stdin: Stdin::Reader(Box::new(from_interactor)),
+                stdout: Sink::Writer(Box::new(to_interactor)),
+                stderr: Sink::Capture,
+            },
+        ),
+        state.run(
+            &dir,
+            Profile::Interact(resource_limits),
+            Streams {
+                stdin: Stdin::Reader(Box::new(from_submission)),
+                stdout: Sink::Writer(Box::new(to_submission)),
+                stderr: Sink::Capture,
+            },
+        ),
+    );
+
+    if let Err(e) = fs::remove_dir_all(&dir).await {
+        tracing::warn!("failed to remove interactor directory: {e}");
+    }
+
+    let interactor_output = interactor_output.wrap_err("failed to execute interactor")?;
+    tracing::trace!("interactor used {:?}", interactor_output.resource_usage());
+
+    Ok((output?, interactor_output))
+}
+
+/// How a checker judged an output.
+struct Checked {
+    verdict: Verdict,
+    score: Option<u32>,
+    message: String,
+}
+
+/// Runs the task's `checker` on the submission's `output`, which is given the test's files in a
+/// directory of its own.
+async fn check(
+    state: &State,
+    checker: &Checker,
+    test: &Test,
+    output: &[u8],
+) -> color_eyre::Result<Checked> {
+    let language = state
+        .config
+        .language(&checker.language)
+        .expect("checker language not validated");
+    let resource_limits = checker
+        .resource_limits
+        .unwrap_or(state.config.resource_limits);
+
+    // Outside of the submission's directory, like the interactor's
+    let dir = state
+        .dir
+        .with_extension(format!("checker-{}", Uuid::new_v4()));
+    fs::create_dir(&dir)
+        .await
+        .wrap_err("failed to create checker directory")?;
+
+    let result = async {
+        let dir = fs::canonicalize(&dir).await?;
+        let paths = [dir.join("input"), dir.join("output"), dir.join("answer")];
+        test.input.write(&paths[0]).await?;
+        fs::write(&paths[1], output).await?;
+        test.output.write(&paths[2]).await?;
+
+        let mut command = language.run.clone();
+        for path in paths {
+            command.push(
+                path.into_os_string()
+                    .into_string()
+                    .map_err(|_| eyre!("non UTF-8 checker directory"))?,
+            );
+        }
+
+        // Trusted like a compiler, and run once the submission no longer needs its CPU
+        let output = state
+            .sandbox
+            .run(
+                &checker.dir,
+                &command,
+                Streams::new(&[][..]),
+                Profile::Check(resource_limits),
+                &state.slot,
+            )
+            .await?;
+        Ok::<_, color_eyre::Report>(output)
+    }
+    .await;
+
+    if let Err(e) = fs::remove_dir_all(&dir).await {
+        tracing::warn!("failed to remove checker directory: {e}");
+    }
+
+    let output = result.wrap_err("failed to execute checker")?;
+    tracing::trace!("checker used {:?}", output.resource_usage());
+
+    let message = String::from_utf8_lossy(output.stderr()).trim().to_owned();
+    if output.timed_out() {
+        bail!("checker timed out: {message}");
+    }
+
+    let judged = match output.exit_status().code() {
+        Some(code) => testlib_verdict(code, &message).wrap_err("checker awarded invalid points")?,
+        None => None,
+    };
+    let Some((verdict, score)) = judged else {
+        bail!("checker failed ({}): {message}", output.exit_status());
+    };
+
+    Ok(Checked {
+        verdict,
+        score,
+        message,
     })
 }
+
+/// The verdict and percentage of the test's score awarded by a program following the conventions
+/// of testlib through its exit `code` and the `message` on its stderr, or `None` if the code isn't
+/// a verdict, such as 3 for a failed check.
+fn testlib_verdict(code: i32, message: &str) -> color_eyre::Result<Option<(Verdict, Option<u32>)>> {
+    let partial = |score| {
+        let verdict = if score == 100 {
+            Verdict::Accepted
+        } else {
+            Verdict::WrongAnswer
+        };
+        (verdict, Some(score))
+    };
+
+    Ok(Some(match code {
+        0 => (Verdict::Accepted, None),
+        1 => (Verdict::WrongAnswer, None),
+        // `_pe`, `_dirt` for extra output, and `_unexpected_eof` for output which ends early
+        2 | 4 | 8 => (Verdict::PresentationError, None),
+        7 => partial(points_score(message).ok_or_else(|| eyre!("invalid points: {message}"))?),
+        16..=116 => partial(code as u32 - 16),
+        _ => return Ok(None),
+    }))
+}
+
+/// The percentage awarded by a checker's `points` message, which testlib starts with the
+/// fraction of the test's score.
+fn points_score(message: &str) -> Option<u32> {
+    let points = message
+        .strip_prefix("points ")?
+        .split_whitespace()
+        .next()?
+        .parse::<f64>()
+        .ok()?;
+
+    (0.0..=1.0)
+        .contains(&points)
+        .then(|| (points * 100.0).round() as u32)
+}
+
+/// Compiles the checkers of `contest` in a directory of their own under `dir`, replacing any
+/// compiled before, each in a slot of `slots`.
+pub async fn compile_checkers(
+    contest: &mut Contest,
+    dir: &Path,
+    sandbox: &dyn Sandbox,
+    slots: &SlotPool,
+) -> color_eyre::Result<()> {
+    let config = &contest.config;
+
+    for (index, task) in contest.tasks.iter_mut().enumerate() {
+        let Some(checker) = &mut task.checker else {
+            continue;
+        };
+        let language = config
+            .language(&checker.language)
+            .expect("checker language not validated");
+
+        let checker_dir = dir.join((index + 1).to_string());
+        if fs::try_exists(&checker_dir).await? {
+            fs::remove_dir_all(&checker_dir)
+                .await
+                .wrap_err("failed to remove previous checker")?;
+        }
+        fs::create_dir_all(&checker_dir)
+            .await
+            .wrap_err("failed to create checker directory")?;
+        fs::copy(&checker.source, checker_dir.join(&language.filename))
+            .await
+            .wrap_err_with(|| {
+                format!("failed to copy checker source {}", checker.source.display())
+            })?;
+
+        if let Some(compile) = &language.compile {
+            let slot = slots.acquire().await;
+            slot.chown(&checker_dir)
+                .wrap_err("failed to hand checker over to its slot")?;
+
+            let resource_limits = language
+                .compile_resource_limits
+                .unwrap_or(config.compile_resource_limits);
+            let output = sandbox
+                .run(
+                    &checker_dir,
+                    compile,
+                    Streams::new(&[][..]),
+                    Profile::Check(resource_limits),
+                    &slot,
+                )
+                .await
+                .wrap_err("failed to execute compile command")?;
+
+            if !output.exit_status().success() {
+                bail!(
+                    "checker of task {} failed to compile ({}): {}",
+                    task.name,
+                    output.exit_status(),
+                    String::from_utf8_lossy(output.stderr())
+                );
+            }
+
+            // Run by every slot, which mustn't be able to replace it
+            slot.release(&checker_dir)
+                .wrap_err("failed to take checker back from its slot")?;
+        }
+
+        checker.dir = fs::canonicalize(&checker_dir).await?;
+        tracing::info!("compiled checker of task {}", task.name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitStatus;
+
+    use axum::response::{IntoResponse, Sse};
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::ReceiverStream;
+
+    use super::*;
+    use crate::sandbox::Fake;
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn contest(tests: Value) -> Value {
+        json!({
+            "name": "Test",
+            "duration": 60,
+            "submission-cooldown": 0,
+            "page": "",
+            "scoring": { "answer-score": 1, "test-score": 1, "subtask-score": 10 },
+            "tasks": [{
+                "name": "Double",
+                "difficulty": "Easy",
+                "page": "",
+                "subtasks": [{ "tests": tests }],
+            }],
+            "judge": {
+                "skip-count": 20,
+                "resource-limits": { "cpu": 1, "memory": 1 << 20 },
+                "languages": [{
+                    "name": "fake",
+                    "filename": "main.fake",
+                    "compile": ["compile"],
+                    "run": ["./main"],
+                }],
+            },
+        })
+    }
+
+    /// Submits to the only task of `contest`, running every command with `sandbox`, and returns
+    /// the messages sent.
+    async fn submit_to(contest: Value, sandbox: Fake) -> Vec<Value> {
+        let dir = std::env::temp_dir().join(format!("judge-test-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).await.unwrap();
+        fs::write(dir.join("main.fake"), "").await.unwrap();
+
+        let contest = Box::leak(Box::new(Contest::load(&contest.to_string(), &dir).unwrap()));
+        let sandbox = Box::leak(Box::new(sandbox));
+        let slots = Box::leak(Box::new(SlotPool::unprivileged(1)));
+
+        let (tx, rx) = mpsc::channel(64);
+        submit(
+            tx,
+            &dir,
+            contest,
+            &contest.tasks[0],
+            &contest.config.languages[0],
+            sandbox,
+            slots,
+        )
+        .await;
+        fs::remove_dir_all(&dir).await.unwrap();
+
+        let response = Sse::new(ReceiverStream::new(rx)).into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect()
+    }
+
+    /// A submission which doubles the number it reads, or behaves as told by its input.
+    fn doubling(_: &Path, _: &[String], stdin: &[u8], profile: &Profile) -> io::Result<Output> {
+        if let Profile::Compile(_) = profile {
+            return Ok(Output::new(exited(0), vec![], vec![]));
+        }
+
+        let output = match str::from_utf8(stdin).unwrap().trim() {
+            // Charged just short of the limit it was killed by
+            "loop" => Output::new(ExitStatus::from_raw(libc::SIGXCPU), vec![], vec![])
+                .with_resource_usage(ResourceUsage {
+                    user_time: Duration::from_millis(997),
+                    ..ResourceUsage::default()
+                }),
+            "spin" => Output::new(ExitStatus::from_raw(libc::SIGKILL), vec![], vec![])
+                .with_resource_usage(ResourceUsage {
+                    user_time: Duration::from_secs(2),
+                    ..ResourceUsage::default()
+                })
+                .with_timed_out(true),
+            "idle" => Output::new(ExitStatus::from_raw(libc::SIGKILL), vec![], vec![])
+                .with_timed_out(true),
+            "allocate" => Output::new(ExitStatus::from_raw(libc::SIGKILL), vec![], vec![])
+                .with_memory_limit_exceeded(true),
+            "print" => Output::new(ExitStatus::from_raw(libc::SIGKILL), vec![], vec![])
+                .with_output_limit_exceeded(true),
+            "fork" => Output::new(exited(1), vec![], vec![]).with_process_limit_exceeded(true),
+            "recurse" => Output::new(ExitStatus::from_raw(libc::SIGSEGV), vec![], vec![])
+                .with_stack_limit_exceeded(true),
+            "write" => Output::new(ExitStatus::from_raw(libc::SIGXFSZ), vec![], vec![])
+                .with_file_size_limit_exceeded(true),
+            "open" => Output::new(exited(1), vec![], vec![]).with_open_file_limit_exceeded(true),
+            "exec" => Output::new(ExitStatus::from_raw(libc::SIGKILL), vec![], vec![])
+                .with_security_violation(Some(Syscall {
+                    number: libc::SYS_execve,
+                    name: Some("execve"),
+                })),
+            "crash" => Output::new(exited(1), vec![], vec![]),
+            input => {
+                let doubled = input.parse::<i32>().unwrap() * 2;
+                Output::new(exited(0), doubled.to_string().into_bytes(), vec![])
+            }
+        };
+
+        Ok(output)
+    }
+
+    fn report(messages: &[Value]) -> &Value {
+        let done = messages.last().unwrap();
+        assert_eq!(done["type"], "Done", "{messages:?}");
+        &done["report"]
+    }
+
+    #[tokio::test]
+    async fn judges_outputs() {
+        let messages = submit_to(
+            contest(json!([
+                { "input": "1", "output": "2" },
+                { "input": "2", "output": "5" },
+            ])),
+            Fake::new(doubling),
+        )
+        .await;
+
+        assert_eq!(messages[0], json!({ "type": "Queued", "tests": 2 }));
+        assert_eq!(messages[1], json!({ "type": "Compiling" }));
+        let report = report(&messages);
+        assert_eq!(report["task"], "WrongAnswer");
+        assert_eq!(report["tests"][0][0]["verdict"], "Accepted");
+        assert_eq!(report["tests"][0][1]["verdict"], "WrongAnswer");
+        assert_eq!(report["score"], 1.0);
+    }
+
+    #[tokio::test]
+    async fn judges_exceeded_limits() {
+        let inputs = [
+            ("loop", "TimeLimitExceeded"),
+            ("spin", "TimeLimitExceeded"),
+            ("idle", "IdlenessLimitExceeded"),
+            ("allocate", "MemoryLimitExceeded"),
+            ("print", "OutputLimitExceeded"),
+            ("fork", "ProcessLimitExceeded"),
+            ("recurse", "StackLimitExceeded"),
+            ("write", "FileSizeLimitExceeded"),
+            ("open", "OpenFileLimitExceeded"),
+            ("exec", "SecurityViolation"),
+            ("crash", "RuntimeError"),
+        ];
+        let tests = inputs
+            .iter()
+            .map(|(input, _)| json!({ "input": input, "output": "" }))
+            .collect();
+
+        let messages = submit_to(contest(Value::Array(tests)), Fake::new(doubling)).await;
+
+        let report = report(&messages);
+        for (i, (input, verdict)) in inputs.iter().enumerate() {
+            assert_eq!(report["tests"][0][i]["verdict"], *verdict, "{input}");
+        }
+        assert_eq!(
+            report["tests"][0][9]["security_violation"],
+            json!({ "number": libc::SYS_execve, "name": "execve" })
+        );
+        assert_eq!(report["score"], 0.0);
+    }
+
+    fn tested(score: f64) -> TestReport {
+        TestReport {
+            verdict: if score == 1.0 {
+                Verdict::Accepted
+            } else {
+                Verdict::WrongAnswer
+            },
+            resource_usage: ResourceUsage::default(),
+            time_measurement: TimeMeasurement::Cpu,
+            security_violation: None,
+            score,
+            message: None,
+        }
+    }
+
+    #[test]
+    fn aggregates_partial_scores() {
+        let scoring = Scoring {
+            answer_score: 0,
+            test_score: 1,
+            subtask_score: 10,
+        };
+        let report = Report::new(
+            Verdict::WrongAnswer,
+            vec![
+                Verdict::Accepted,
+                Verdict::WrongAnswer,
+                Verdict::WrongAnswer,
+            ],
+            vec![
+                vec![tested(1.0), tested(1.0)],
+                // Worth as much as its worst test, on top of the points of each test
+                vec![tested(1.0), tested(0.5)],
+                vec![tested(0.0), tested(1.0)],
+                vec![],
+            ],
+            &scoring,
+        );
+
+        assert_eq!(report.subtask_scores, [12.0, 6.5, 1.0, 0.0]);
+        assert_eq!(report.score, 19.5);
+    }
+
+    #[test]
+    fn scores_tests_or_subtasks_alone() {
+        let tests = || vec![vec![tested(0.25), tested(1.0)], vec![tested(1.0)]];
+
+        let by_test = Scoring {
+            answer_score: 0,
+            test_score: 4,
+            subtask_score: 0,
+        };
+        let report = Report::new(Verdict::WrongAnswer, vec![], tests(), &by_test);
+        assert_eq!(report.subtask_scores, [5.0, 4.0]);
+        assert_eq!(report.score, 9.0);
+
+        let by_subtask = Scoring {
+            answer_score: 0,
+            test_score: 0,
+            subtask_score: 4,
+        };
+        let report = Report::new(Verdict::WrongAnswer, vec![], tests(), &by_subtask);
+        assert_eq!(report.subtask_scores, [1.0, 4.0]);
+        assert_eq!(report.score, 5.0);
+    }
+
+    #[test]
+    fn scores_answers() {
+        let scoring = Scoring {
+            answer_score: 7,
+            test_score: 1,
+            subtask_score: 10,
+        };
+
+        assert_eq!(Report::answer(Verdict::Accepted, &scoring).score, 7.0);
+        assert_eq!(Report::answer(Verdict::WrongAnswer, &scoring).score, 0.0);
+    }
+
+    #[test]
+    fn maps_testlib_exit_codes() {
+        let verdict = |code, message| testlib_verdict(code, message).unwrap();
+
+        assert_eq!(verdict(0, ""), Some((Verdict::Accepted, None)));
+        assert_eq!(verdict(1, "wrong"), Some((Verdict::WrongAnswer, None)));
+        assert_eq!(verdict(2, ""), Some((Verdict::PresentationError, None)));
+        assert_eq!(verdict(4, ""), Some((Verdict::PresentationError, None)));
+        assert_eq!(verdict(8, ""), Some((Verdict::PresentationError, None)));
+        assert_eq!(verdict(3, "fail"), None);
+        assert_eq!(verdict(-1, ""), None);
+        assert_eq!(
+            verdict(7, "points 0.25 close"),
+            Some((Verdict::WrongAnswer, Some(25)))
+        );
+        assert_eq!(verdict(7, "points 1"), Some((Verdict::Accepted, Some(100))));
+        assert!(testlib_verdict(7, "points 2").is_err());
+        assert!(testlib_verdict(7, "").is_err());
+        assert_eq!(verdict(16 + 40, ""), Some((Verdict::WrongAnswer, Some(40))));
+        assert_eq!(verdict(116, ""), Some((Verdict::Accepted, Some(100))));
+        assert_eq!(verdict(117, ""), None);
+    }
+
+    #[tokio::test]
+    async fn reports_compile_errors() {
+        let messages = submit_to(
+            contest(json!([{ "input": "1", "output": "2" }])),
+            Fake::new(|_, _, _, _| Ok(Output::new(exited(1), vec![], b"error".to_vec()))),
+        )
+        .await;
+
+        assert_eq!(
+            messages[2],
+            json!({ "type": "Compiled", "exit_code": 1, "stderr": "error", "timed_out": false })
+        );
+        let report = report(&messages);
+        assert_eq!(report["task"], "CompileError");
+        assert_eq!(report["tests"][0][0]["verdict"], "CompileError");
+    }
+
+    #[tokio::test]
+    async fn reports_compile_timeouts() {
+        let messages = submit_to(
+            contest(json!([{ "input": "1", "output": "2" }])),
+            Fake::new(|_, _, _, _| {
+                Ok(Output::new(
+                    ExitStatus::from_raw(libc::SIGXCPU),
+                    vec![],
+                    vec![],
+                ))
+            }),
+        )
+        .await;
+
+        assert_eq!(
+            messages[2],
+            json!({ "type": "Compiled", "exit_code": -1, "stderr": "", "timed_out": true })
+        );
+    }
+}
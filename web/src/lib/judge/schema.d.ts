@@ -18,6 +18,7 @@ export type Message =
   | {
       exit_code: number;
       stderr: string;
+      timed_out: boolean;
       type: "Compiled";
       [k: string]: unknown;
     }
@@ -43,27 +44,70 @@ export type Message =
     };
 export type Verdict =
   | "CompileError"
+  | "SecurityViolation"
   | "RuntimeError"
   | "WrongAnswer"
+  | "PresentationError"
   | "TimeLimitExceeded"
+  | "IdlenessLimitExceeded"
   | "MemoryLimitExceeded"
+  | "OutputLimitExceeded"
+  | "ProcessLimitExceeded"
+  | "StackLimitExceeded"
+  | "FileSizeLimitExceeded"
+  | "OpenFileLimitExceeded"
   | "Skipped"
   | "Accepted";
+export type TimeMeasurement =
+  | "cpu"
+  | {
+      instructions: {
+        /**
+         * Instructions per second of user time
+         */
+        "per-second": number;
+        [k: string]: unknown;
+      };
+    };
 
 export interface Report {
+  /**
+   * Points awarded for the task, the sum of `subtask_scores`
+   */
+  score: number;
+  /**
+   * Points awarded for each subtask, including the points of its tests
+   */
+  subtask_scores: number[];
   subtasks: Verdict[];
   task: Verdict;
   tests: TestReport[][];
   [k: string]: unknown;
 }
 export interface TestReport {
+  /**
+   * What the checker of the task reported about the output
+   */
+  message?: string | null;
   resource_usage: ResourceUsage;
+  /**
+   * Fraction of the test's score awarded, which the interactor or checker of the task may only award in part
+   */
+  score: number;
+  /**
+   * The forbidden syscall made by a submission judged as a security violation
+   */
+  security_violation?: Syscall | null;
+  /**
+   * How the CPU time in `resource_usage` was measured, which is `cpu` if instructions couldn't be counted. With `instructions` it's all user time.
+   */
+  time_measurement: TimeMeasurement;
   verdict: Verdict;
   [k: string]: unknown;
 }
 export interface ResourceUsage {
   /**
-   * Memory usage (bytes)
+   * Peak resident memory (bytes)
    */
   memory: number;
   /**
@@ -74,6 +118,10 @@ export interface ResourceUsage {
    * User time
    */
   "user-time": Duration;
+  /**
+   * Wall-clock time
+   */
+  "wall-time": Duration;
   [k: string]: unknown;
 }
 export interface Duration {
@@ -81,3 +129,11 @@ export interface Duration {
   secs: number;
   [k: string]: unknown;
 }
+/**
+ * A syscall made in violation of a seccomp policy
+ */
+export interface Syscall {
+  name?: string | null;
+  number: number;
+  [k: string]: unknown;
+}
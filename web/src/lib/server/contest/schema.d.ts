@@ -5,7 +5,74 @@
  * and run json-schema-to-typescript to regenerate this file.
  */
 
+export type MemoryMode = "cgroup" | "address-space" | "data";
+export type StackLimit =
+  | {
+      bytes: number;
+    }
+  | "unlimited";
+export type TimeMeasurement =
+  | "cpu"
+  | {
+      instructions: {
+        /**
+         * Instructions per second of user time
+         */
+        "per-second": number;
+        [k: string]: unknown;
+      };
+    };
+export type SyscallRule =
+  | string
+  | {
+      args: ArgCondition[];
+      syscall: string;
+      [k: string]: unknown;
+    };
+/**
+ * Argument size, defaults to qword
+ */
+export type ArgLen = "dword" | "qword";
+export type ArgOp =
+  | ("eq" | "ne" | "lt" | "le" | "gt" | "ge")
+  | {
+      "masked-eq": number;
+    };
+export type OnViolation = "deny" | "kill";
+export type Answer = string | string[];
+/**
+ * How the output of a submission is compared to the expected output. Both are compared as bytes, so output which isn't valid UTF-8 can still be accepted.
+ */
+export type Compare =
+  | "trimmed"
+  | "exact"
+  | "tokens"
+  | "lines"
+  | {
+      float: {
+        absolute?: number;
+        relative?: number;
+        [k: string]: unknown;
+      };
+    }
+  | "case-insensitive"
+  | "unordered-lines";
 export type Difficulty = "Easy" | "Medium" | "Hard";
+export type Io =
+  | "stdio"
+  | {
+      file: {
+        input: string;
+        output: string;
+        [k: string]: unknown;
+      };
+    };
+export type TestData =
+  | string
+  | {
+      file: string;
+      [k: string]: unknown;
+    };
 
 export interface Contest {
   duration: number;
@@ -18,35 +85,123 @@ export interface Contest {
   [k: string]: unknown;
 }
 export interface Config {
+  /**
+   * Resource limits of compilers, where the process limit defaults to 64 instead, since compilers such as gcc run several programs
+   */
+  "compile-resource-limits"?: ResourceLimits;
   languages: Language[];
   "resource-limits": ResourceLimits;
+  "seccomp-policies"?: {
+    [k: string]: SeccompPolicy;
+  };
   "skip-count": number;
   [k: string]: unknown;
 }
 export interface Language {
   compile?: string[] | null;
+  /**
+   * Overrides the contest's compile resource limits, with the same default process limit
+   */
+  "compile-resource-limits"?: ResourceLimits | null;
   filename: string;
   name: string;
+  /**
+   * Overrides the contest's process and thread limit, for runtimes or submissions which are allowed to be multithreaded
+   */
+  processes?: number | null;
   run: string[];
+  /**
+   * Name of the seccomp policy applied to submissions, defaults to the built-in `default`
+   */
+  "seccomp-policy"?: string | null;
+  /**
+   * Absolute paths made visible read-only to submissions, such as the interpreter and shared libraries. Nothing else on the host is visible besides the submission directory.
+   */
+  toolchain?: string[];
   [k: string]: unknown;
 }
 export interface ResourceLimits {
+  /**
+   * Core dump size (bytes), disabled by default
+   */
+  core?: number;
   /**
    * CPU time (seconds)
    */
   cpu: number;
   /**
-   * CPU time tolerance (seconds)
+   * CPU time tolerance (seconds): usage this close to the limit also counts as exceeding it
+   */
+  "cpu-tolerance"?: number;
+  /**
+   * Size of each file written (bytes)
    */
-  "cpu-tolerance": number;
+  "file-size"?: number;
   /**
    * Memory usage (bytes)
    */
   memory: number;
   /**
-   * Memory usage tolerance (bytes)
+   * How the memory limit is enforced
+   */
+  "memory-mode"?: MemoryMode;
+  /**
+   * Memory usage tolerance (bytes): usage this close to the limit also counts as exceeding it
+   */
+  "memory-tolerance"?: number;
+  /**
+   * Open file descriptors of each process, including standard input and output
+   */
+  "open-files"?: number;
+  /**
+   * Processes and threads, defaults to a single-threaded process
+   */
+  processes?: number;
+  /**
+   * Stack size of each process, defaults to 8 MiB
+   */
+  stack?: StackLimit;
+  /**
+   * Standard error size (bytes)
+   */
+  stderr?: number;
+  /**
+   * Standard output size (bytes)
+   */
+  stdout?: number;
+  /**
+   * How the CPU time of submissions is measured
+   */
+  "time-measurement"?: TimeMeasurement;
+  /**
+   * Wall-clock time (seconds), defaults to twice the CPU time plus one second
+   */
+  wall?: number | null;
+  [k: string]: unknown;
+}
+/**
+ * A named set of allowed syscalls, declared in the contest configuration.
+ */
+export interface SeccompPolicy {
+  allow?: SyscallRule[];
+  /**
+   * Policy whose syscalls are also allowed by this one, such as the built-in `default` policy
    */
-  "memory-tolerance": number;
+  extends?: string | null;
+  /**
+   * What happens when a syscall outside the policy is made, not inherited from `extends`
+   */
+  "on-violation"?: OnViolation;
+  [k: string]: unknown;
+}
+export interface ArgCondition {
+  /**
+   * Argument index (0-5)
+   */
+  index: number;
+  len?: ArgLen;
+  op: ArgOp;
+  value: number;
   [k: string]: unknown;
 }
 export interface Scoring {
@@ -56,19 +211,86 @@ export interface Scoring {
   [k: string]: unknown;
 }
 export interface Task {
-  answer?: string | null;
+  /**
+   * Makes the task accept answers, which are compared to this answer
+   */
+  answer?: Answer | null;
+  /**
+   * How submitted answers are compared to `answer`
+   */
+  "answer-compare"?: Compare;
+  /**
+   * Judges outputs with this checker instead of comparing them to the expected output, for tasks with several valid answers
+   */
+  checker?: Checker | null;
+  /**
+   * How outputs are compared to the expected output, unless the task has a checker
+   */
+  compare?: Compare;
   difficulty: Difficulty;
+  /**
+   * Makes the task interactive: instead of comparing its output, each test connects the submission to this interactor
+   */
+  interactor?: Interactor | null;
+  /**
+   * How submissions read the input and write their output, standard I/O by default
+   */
+  io?: Io;
   name: string;
   page: string;
   subtasks: Subtask[];
   [k: string]: unknown;
 }
+/**
+ * An author-supplied program which judges the output of each test, following the conventions of testlib so that existing checkers work unchanged.
+ *
+ * It's compiled once when the contest is loaded, then run as `checker input output answer` with the paths of the test's input, the submission's output and the expected output. Its exit code decides the verdict: 0 for Accepted, 1 for Wrong Answer, 2, 4 or 8 for Presentation Error, 7 for the fraction of the test's score following `points` on its stderr, and 16 plus a percentage of the test's score. Any other outcome, such as 3 for a failed check, is an error of the judge. Its stderr is reported along with the verdict.
+ */
+export interface Checker {
+  /**
+   * Name of the contest language which compiles and runs the checker
+   */
+  language: string;
+  /**
+   * Overrides the contest's resource limits, the language's compile resource limits still apply when compiling it
+   */
+  "resource-limits"?: ResourceLimits | null;
+  /**
+   * Source file, relative to the contest file
+   */
+  source: string;
+  [k: string]: unknown;
+}
+/**
+ * An author-supplied program which talks to the submission, its stdout feeding the submission's stdin and the other way around.
+ *
+ * It runs in a directory of its own containing the test's `input` and expected `output`, and decides the verdict and any partial score only through its exit code, like a [`Checker`], but Accepted becomes Runtime Error if the submission failed. Exceeding its wall-clock limit is blamed on the submission being idle, and any other outcome is an error of the judge.
+ */
+export interface Interactor {
+  /**
+   * Trusted command, which sees the host's filesystem read-only. A program given as a relative path, such as `./interactor`, is relative to the contest file.
+   */
+  command: string[];
+  /**
+   * Overrides the contest's resource limits, which apply to the submission separately
+   */
+  "resource-limits"?: ResourceLimits | null;
+  [k: string]: unknown;
+}
 export interface Subtask {
-  tests: Test[];
+  /**
+   * Pattern of input files relative to the contest file, such as `tests/1-*.in`, whose expected outputs are the files with the same name and the `.out` extension. Each match is added as a test after those listed, in alphabetical order.
+   */
+  glob?: string | null;
+  tests?: Test[];
   [k: string]: unknown;
 }
 export interface Test {
-  input: string;
-  output: string;
+  /**
+   * Overrides how the task compares the output of this test
+   */
+  compare?: Compare | null;
+  input: TestData;
+  output: TestData;
   [k: string]: unknown;
 }
@@ -33,10 +33,18 @@ export const contests = sqliteTable('contests', {
 const verdict = text('verdict', {
 	enum: [
 		'CompileError',
+		'SecurityViolation',
 		'RuntimeError',
-		'MemoryLimitExceeded',
-		'TimeLimitExceeded',
 		'WrongAnswer',
+		'PresentationError',
+		'TimeLimitExceeded',
+		'IdlenessLimitExceeded',
+		'MemoryLimitExceeded',
+		'OutputLimitExceeded',
+		'ProcessLimitExceeded',
+		'StackLimitExceeded',
+		'FileSizeLimitExceeded',
+		'OpenFileLimitExceeded',
 		'Skipped',
 		'Accepted'
 	]
@@ -56,7 +64,7 @@ export const submissions = sqliteTable('submissions', {
 	task: integer('task').notNull(),
 	code: text('code'),
 	language: text('language'),
-	score: integer('score').notNull(),
+	score: real('score').notNull(),
 	verdict
 });
 
@@ -3,8 +3,9 @@ import { db } from '$lib/server/db';
 import { submissions, contests } from '$lib/server/db/schema';
 import { eq, and, desc } from 'drizzle-orm';
 import { error } from '@sveltejs/kit';
+import { createParser } from 'eventsource-parser';
 import type { Actions, PageServerLoad } from './$types';
-import type { Verdict } from '$lib/judge/schema';
+import type { Message, Report } from '$lib/judge/schema';
 
 // TODO: deduplicate loading, server-side validate submission cooldown
 
@@ -75,7 +76,7 @@ export const load: PageServerLoad = async ({ params, locals }) => {
 };
 
 export const actions: Actions = {
-	submitAnswer: async ({ params, request, locals }) => {
+	submitAnswer: async ({ fetch, params, request, locals }) => {
 		const formData = await request.formData();
 		const answer = formData.get('answer')?.toString();
 
@@ -85,19 +86,39 @@ export const actions: Actions = {
 
 		const index = parseInt(params.task);
 		const task = contestData!.tasks[index - 1];
-		if (!answer || !task.answer) error(400);
+		if (!answer || task.answer == null) error(400);
 
-		let verdict: Verdict = 'WrongAnswer';
-		if (answer.trim() === task.answer.trim()) verdict = 'Accepted';
+		const body = new FormData();
+		body.set('contest', params.contest);
+		body.set('task', params.task);
+		body.set('answer', answer);
 
-		db.insert(submissions).values({
+		const response = await fetch('http://judge:8128', {
+			method: 'POST',
+			body
+		});
+
+		if (!response.ok) error(response.status, await response.text());
+
+		let report: Report | undefined;
+		const parser = createParser({
+			onEvent: (event) => {
+				const message: Message = JSON.parse(event.data);
+				if (message.type === 'Done') report = message.report;
+			}
+		});
+		parser.feed(await response.text());
+
+		if (!report) error(500, 'judge did not report a verdict');
+
+		await db.insert(submissions).values({
 			userId: locals.user!.id,
 			contestId: contest!.id,
 			task: index,
-			score: contestData!.scoring['answer-score'],
-			verdict
+			score: report.score,
+			verdict: report.task
 		});
 
-		return { verdict };
+		return { verdict: report.task };
 	}
 };
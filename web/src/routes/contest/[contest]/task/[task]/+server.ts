@@ -42,21 +42,13 @@ export const POST: RequestHandler = async ({ fetch, request, params, locals }) =
 			if (message.type === 'Done') {
 				const report = message.report;
 
-				const subtaskScore = report.subtasks
-					.map((verdict) => (verdict === 'Accepted' ? contestData.scoring['subtask-score'] : 0))
-					.reduce((acc, subtaskScore) => acc + subtaskScore, 0);
-				const testScore =
-					report.tests.flatMap((subtask) => subtask).filter((test) => test.verdict === 'Accepted')
-						.length * contestData.scoring['test-score'];
-				const score = subtaskScore + testScore;
-
 				const submission = await db.insert(submissions).values({
 					userId: locals.user!.id,
 					contestId: contest.id,
 					task: parseInt(params.task),
 					code,
 					language: formData.get('language')!.toString(),
-					score,
+					score: report.score,
 					verdict: report.task
 				});
 